@@ -0,0 +1,134 @@
+//! Throughput of tiny jobs: `ThreadPool` against the old `Mutex<mpsc::Receiver>` design.
+//!
+//! Run with `cargo bench`. `BENCH_JOBS` sets the number of jobs per run.
+
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rust_thread_pool::ThreadPool;
+
+/// The pool before the `JobQueue` rewrite: every worker takes the receiver
+/// lock and holds it while blocked in `recv()`.
+mod baseline {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    enum Message {
+        NewJob(Job),
+        Terminate,
+    }
+
+    pub struct ThreadPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: mpsc::Sender<Message>,
+    }
+
+    impl ThreadPool {
+        pub fn new(size: usize) -> ThreadPool {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver: Arc<Mutex<mpsc::Receiver<Message>>> = Arc::clone(&receiver);
+
+                    thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv().unwrap();
+
+                        match message {
+                            Message::NewJob(job) => job(),
+                            Message::Terminate => break,
+                        }
+                    })
+                })
+                .collect();
+
+            ThreadPool { workers, sender }
+        }
+
+        pub fn run<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            for _ in &self.workers {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+
+            for handle in self.workers.drain(..) {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
+
+/// Runs `jobs` tiny jobs through `run` and waits until all of them are done.
+fn measure<R>(jobs: usize, run: R) -> Duration
+where
+    R: Fn(Box<dyn FnOnce() + Send + 'static>),
+{
+    let done = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    for _ in 0..jobs {
+        let done = Arc::clone(&done);
+
+        run(Box::new(move || {
+            done.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+
+    while done.load(Ordering::Relaxed) < jobs {
+        thread::yield_now();
+    }
+
+    start.elapsed()
+}
+
+fn main() {
+    let jobs = env::var("BENCH_JOBS")
+        .ok()
+        .and_then(|jobs| jobs.parse().ok())
+        .unwrap_or(200_000);
+
+    println!("{} tiny jobs per run, in jobs per second", jobs);
+    println!(
+        "{:>8} {:>14} {:>14} {:>8}",
+        "threads", "baseline", "ThreadPool", "ratio"
+    );
+
+    for &threads in &[1, 2, 4, 8, 16, 32, 64] {
+        let baseline = {
+            let pool = baseline::ThreadPool::new(threads);
+
+            measure(jobs, |job| pool.run(job))
+        };
+
+        let current = {
+            let pool = ThreadPool::new(threads);
+
+            measure(jobs, |job| pool.run(job))
+        };
+
+        let per_second = |elapsed: Duration| jobs as f64 / elapsed.as_secs_f64();
+
+        println!(
+            "{:>8} {:>14.0} {:>14.0} {:>7.2}x",
+            threads,
+            per_second(baseline),
+            per_second(current),
+            baseline.as_secs_f64() / current.as_secs_f64()
+        );
+    }
+}
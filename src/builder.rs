@@ -0,0 +1,219 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::event::Sink;
+use crate::queue::QueueConfig;
+use crate::{Event, OverflowPolicy, PoolBuildError, ThreadPool};
+
+/// How long an extra worker waits for a job before exiting, unless
+/// `ThreadPoolBuilder::keep_alive` says otherwise.
+pub(crate) const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Per-thread settings collected by `ThreadPoolBuilder`.
+///
+/// # Notes
+///
+/// Kept in `Shared` after `build()`, since respawned workers need them too.
+/// So are `max_threads` and `keep_alive`, for the workers spawned on demand.
+#[derive(Default)]
+pub(crate) struct ThreadConfig {
+    pub(crate) thread_name: Option<Box<dyn Fn(usize) -> String + Send + Sync>>,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) on_thread_start: Option<Box<dyn Fn(usize) + Send + Sync>>,
+    pub(crate) on_thread_stop: Option<Box<dyn Fn(usize) + Send + Sync>>,
+    pub(crate) sink: Sink,
+    pub(crate) max_threads: Option<usize>,
+    pub(crate) keep_alive: Option<Duration>,
+}
+
+impl ThreadConfig {
+    /// Creates the `thread::Builder` for Worker `id`.
+    pub(crate) fn thread_builder(&self, id: usize) -> thread::Builder {
+        let mut builder = thread::Builder::new();
+
+        if let Some(thread_name) = &self.thread_name {
+            builder = builder.name(thread_name(id));
+        }
+
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        builder
+    }
+}
+
+/// Thread Pool Builder.
+///
+/// # Usage
+///
+/// ```
+/// use rust_thread_pool::ThreadPoolBuilder;
+///
+/// let pool = ThreadPoolBuilder::new()
+///     .num_threads(4)
+///     .thread_name(|id| format!("worker-{}", id))
+///     .stack_size(1024 * 1024)
+///     .build()
+///     .unwrap();
+///
+/// pool.run(|| println!("hello from the pool"));
+/// ```
+///
+/// # Notes
+///
+/// The hooks run on the worker thread itself and get the worker `id`. They
+/// must not panic. A worker whose `on_thread_start` panics dies for good,
+/// it isn't respawned, and reports `EventKind::StartFailed`.
+///
+/// Worker `Event`s are dropped unless `on_event` (or `log_events`, with the
+/// `log` feature) is set.
+#[derive(Default)]
+pub struct ThreadPoolBuilder {
+    num_threads: Option<usize>,
+    queue: QueueConfig,
+    config: ThreadConfig,
+}
+
+impl ThreadPoolBuilder {
+    /// Creates a builder with default settings.
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    /// Sets the number of worker threads.
+    ///
+    /// Defaults to `thread::available_parallelism()`, or `1` if unknown.
+    pub fn num_threads(mut self, num_threads: usize) -> ThreadPoolBuilder {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Lets the pool grow past `num_threads`, up to `max_threads` threads.
+    ///
+    /// The `num_threads` core workers are always alive. Whenever a job is
+    /// queued while none of the workers is idle, an extra one is spawned, as
+    /// long as the total stays below `max_threads`. Extra workers exit once
+    /// they've been idle for `keep_alive`.
+    ///
+    /// Off by default, so the pool keeps exactly `num_threads` threads.
+    pub fn max_threads(mut self, max_threads: usize) -> ThreadPoolBuilder {
+        self.config.max_threads = Some(max_threads);
+        self
+    }
+
+    /// Sets how long an extra worker waits for a job before exiting.
+    ///
+    /// Defaults to 60s. Ignored unless `max_threads` is set.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.config.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Bounds the job queue to `queue_capacity` waiting jobs.
+    ///
+    /// The queue is unbounded by default. What happens when it is full is
+    /// decided by `overflow_policy`.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> ThreadPoolBuilder {
+        self.queue.capacity = Some(queue_capacity);
+        self
+    }
+
+    /// Sets what to do when a bounded queue is full.
+    ///
+    /// Defaults to `OverflowPolicy::Block`. Ignored if the queue is unbounded.
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> ThreadPoolBuilder {
+        self.queue.policy = overflow_policy;
+        self
+    }
+
+    /// Gives each worker its own job deque, and lets idle workers steal.
+    ///
+    /// Jobs submitted from inside a worker then go to that worker's deque
+    /// instead of the shared queue, newest first, which suits jobs that
+    /// spawn subtasks recursively. Jobs from other threads still go through
+    /// the shared queue. Off by default.
+    pub fn work_stealing(mut self, work_stealing: bool) -> ThreadPoolBuilder {
+        self.queue.work_stealing = work_stealing;
+        self
+    }
+
+    /// Sets how long a queued job waits before it is treated like a job of
+    /// the next higher `Priority`.
+    ///
+    /// Defaults to 100ms. A shorter period gets `Priority::Low` jobs through
+    /// sooner, a longer one favors `Priority::High` jobs more.
+    pub fn priority_aging(mut self, priority_aging: Duration) -> ThreadPoolBuilder {
+        self.queue.aging = Some(priority_aging);
+        self
+    }
+
+    /// Sets how worker threads are named, from their `id`.
+    pub fn thread_name<F>(mut self, thread_name: F) -> ThreadPoolBuilder
+    where
+        F: Fn(usize) -> String + Send + Sync + 'static,
+    {
+        self.config.thread_name = Some(Box::new(thread_name));
+        self
+    }
+
+    /// Sets the stack size of worker threads, in bytes.
+    pub fn stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.config.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets a callback run on each worker thread before it takes any job.
+    pub fn on_thread_start<F>(mut self, on_thread_start: F) -> ThreadPoolBuilder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.config.on_thread_start = Some(Box::new(on_thread_start));
+        self
+    }
+
+    /// Sets a callback run on each worker thread right before it exits.
+    pub fn on_thread_stop<F>(mut self, on_thread_stop: F) -> ThreadPoolBuilder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.config.on_thread_stop = Some(Box::new(on_thread_stop));
+        self
+    }
+
+    /// Sends worker `Event`s to `on_event` instead of dropping them.
+    ///
+    /// `on_event` is called on the worker threads, in the middle of serving
+    /// jobs, so it should be quick.
+    pub fn on_event<F>(mut self, on_event: F) -> ThreadPoolBuilder
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.config.sink = Sink::Callback(Box::new(on_event));
+        self
+    }
+
+    /// Sends worker `Event`s to the `log` facade, with target `rust_thread_pool`.
+    ///
+    /// Job events are logged at `trace`, so they cost next to nothing unless
+    /// enabled.
+    #[cfg(feature = "log")]
+    pub fn log_events(mut self) -> ThreadPoolBuilder {
+        self.config.sink = Sink::Log;
+        self
+    }
+
+    /// Creates the `ThreadPool`.
+    ///
+    /// # Errors
+    ///
+    /// See `ThreadPool::try_new()`. Also returns `PoolBuildError::MaxBelowSize`
+    /// if `max_threads` is less than `num_threads`.
+    pub fn build(self) -> Result<ThreadPool, PoolBuildError> {
+        let num_threads = self
+            .num_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+        ThreadPool::with_config(num_threads, self.queue, self.config)
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Lets a job started with `ThreadPool::submit_cancellable` check whether
+/// it should stop early.
+///
+/// # Notes
+///
+/// Cancellation is cooperative: nothing stops a running job, it has to check
+/// `is_cancelled()` now and then and return. A token is cancelled by
+/// `TaskHandle::cancel()`, or for every job at once when the pool shuts down.
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+/// What a `CancellationToken` shares with its `TaskHandle`.
+///
+/// # Members
+///
+/// - `cancelled`: Set by `TaskHandle::cancel()`.
+/// - `started`: Set once a worker picks the job up.
+/// - `shutdown`: Set by the pool on shutdown, shared by all of its tokens.
+struct Inner {
+    cancelled: AtomicBool,
+    started: AtomicBool,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is also cancelled once `shutdown` is set.
+    pub(crate) fn new(shutdown: Arc<AtomicBool>) -> CancellationToken {
+        CancellationToken {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                started: AtomicBool::new(false),
+                shutdown,
+            }),
+        }
+    }
+
+    /// Whether the job was cancelled, or the pool is shutting down.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst) || self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the job.
+    ///
+    /// Returns `true` if it hadn't started yet, so it never will.
+    pub(crate) fn cancel(&self) -> bool {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        !self.inner.started.load(Ordering::SeqCst)
+    }
+
+    /// Marks the job as started.
+    ///
+    /// Returns `false` if it was cancelled first and must not run.
+    ///
+    /// # Notes
+    ///
+    /// Pairs with `cancel`: each stores its flag before loading the other,
+    /// so at least one of them sees the other and they never both go ahead.
+    /// Only `cancelled` counts here, so a graceful shutdown still starts the
+    /// queued jobs, which can then check `is_cancelled()`.
+    pub(crate) fn start(&self) -> bool {
+        self.inner.started.store(true, Ordering::SeqCst);
+
+        !self.inner.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,171 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::NodeId;
+
+/// Error returned when waiting on a `TaskHandle`.
+///
+/// # Variants
+///
+/// - `Pending`: `try_join` found the job not finished yet.
+/// - `Timeout`: `join_timeout` gave up before the job finished.
+/// - `Disconnected`: The job was dropped before it produced a value,
+///   or the value was already taken out of the handle.
+/// - `Panicked`: The job panicked, carrying the panic payload.
+/// - `Cancelled`: `TaskHandle::cancel()` was called before the job started.
+#[derive(Debug)]
+pub enum JoinError {
+    Pending,
+    Timeout,
+    Disconnected,
+    Panicked(Box<dyn Any + Send + 'static>),
+    Cancelled,
+}
+
+impl JoinError {
+    /// The panic message, if this is `Panicked` with a string payload.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JoinError::Panicked(payload) => payload
+                .downcast_ref::<&'static str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Pending => write!(f, "job has not finished yet"),
+            JoinError::Timeout => write!(f, "timed out waiting for job"),
+            JoinError::Disconnected => write!(f, "job was dropped without producing a value"),
+            JoinError::Panicked(_) => match self.panic_message() {
+                Some(message) => write!(f, "job panicked: {}", message),
+                None => write!(f, "job panicked"),
+            },
+            JoinError::Cancelled => write!(f, "job was cancelled before it started"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// Error returned by `ThreadPool::try_new()`.
+///
+/// # Variants
+///
+/// - `ZeroSize`: A pool needs at least one thread.
+/// - `ZeroCapacity`: A bounded queue needs room for at least one job.
+/// - `MaxBelowSize`: `max_threads` is smaller than the number of threads.
+/// - `Spawn`: The OS refused to create a worker thread.
+#[derive(Debug)]
+pub enum PoolBuildError {
+    ZeroSize,
+    ZeroCapacity,
+    MaxBelowSize,
+    Spawn(io::Error),
+}
+
+impl fmt::Display for PoolBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolBuildError::ZeroSize => write!(f, "thread pool size must be greater than 0"),
+            PoolBuildError::ZeroCapacity => write!(f, "queue capacity must be greater than 0"),
+            PoolBuildError::MaxBelowSize => {
+                write!(f, "max threads must not be less than the number of threads")
+            }
+            PoolBuildError::Spawn(e) => write!(f, "failed to spawn worker thread: {}", e),
+        }
+    }
+}
+
+impl Error for PoolBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolBuildError::Spawn(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by `TaskGraphBuilder::build()`.
+///
+/// # Variants
+///
+/// - `UnknownNode`: A dependency names a node that isn't in the builder.
+/// - `Cycle`: These nodes are on a cycle, or depend on one, so they could
+///   never run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    UnknownNode(NodeId),
+    Cycle(Vec<NodeId>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::UnknownNode(node) => {
+                write!(f, "task graph has no node {}", node.index())
+            }
+            GraphError::Cycle(nodes) => {
+                let nodes: Vec<usize> = nodes.iter().map(|node| node.index()).collect();
+
+                write!(f, "task graph has a cycle, nodes {:?} can never run", nodes)
+            }
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+/// Error returned by `ThreadPool::try_run()`, handing the job back.
+///
+/// # Variants
+///
+/// - `Closed`: Every worker thread has exited, so the job would never run.
+/// - `Full`: The bounded queue is full and the policy is `Reject`.
+/// - `Shutdown`: The pool was shut down and takes no new jobs.
+///
+/// # Notes
+///
+/// Like `mpsc::SendError`, `Debug` doesn't require the job to be `Debug`.
+pub enum SubmitError<F> {
+    Closed(F),
+    Full(F),
+    Shutdown(F),
+}
+
+impl<F> SubmitError<F> {
+    /// Gets the rejected job back, e.g. to run it somewhere else.
+    pub fn into_inner(self) -> F {
+        match self {
+            SubmitError::Closed(f) | SubmitError::Full(f) | SubmitError::Shutdown(f) => f,
+        }
+    }
+}
+
+impl<F> fmt::Debug for SubmitError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Closed(_) => write!(f, "Closed(..)"),
+            SubmitError::Full(_) => write!(f, "Full(..)"),
+            SubmitError::Shutdown(_) => write!(f, "Shutdown(..)"),
+        }
+    }
+}
+
+impl<F> fmt::Display for SubmitError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Closed(_) => write!(f, "thread pool has no live workers"),
+            SubmitError::Full(_) => write!(f, "thread pool queue is full"),
+            SubmitError::Shutdown(_) => write!(f, "thread pool is shut down"),
+        }
+    }
+}
+
+impl<F> Error for SubmitError<F> {}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::io;
+
+/// What happened in an `Event`.
+///
+/// # Variants
+///
+/// - `JobStarted`: The worker took a job and is running it.
+/// - `JobPanicked`: The job panicked, the worker keeps serving.
+/// - `Terminating`: The worker got `Message::Terminate` and is exiting.
+/// - `ShuttingDown`: The pool is joining the worker thread.
+/// - `Died`: The worker thread died unexpectedly and is being respawned.
+/// - `RespawnFailed`: The replacement thread couldn't be spawned.
+/// - `StartFailed`: The worker thread died before serving any job, e.g. in
+///   `on_thread_start`. It isn't respawned, so the pool runs one short,
+///   though `num_threads()` still counts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    JobStarted,
+    JobPanicked,
+    Terminating,
+    ShuttingDown,
+    Died,
+    RespawnFailed(io::ErrorKind),
+    StartFailed,
+}
+
+/// Something that happened to a worker, handed to the pool's event sink.
+///
+/// # Members
+///
+/// - `worker`: The worker `id`.
+/// - `kind`: What happened.
+///
+/// # Notes
+///
+/// `Display` renders the old `Worker #N: ...` lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub worker: usize,
+    pub kind: EventKind,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Worker #{}: ", self.worker)?;
+
+        match self.kind {
+            EventKind::JobStarted => write!(f, "get job, running."),
+            EventKind::JobPanicked => write!(f, "job panicked, keep serving."),
+            EventKind::Terminating => write!(f, "get signal terminate, terminating."),
+            EventKind::ShuttingDown => write!(f, "shutting down."),
+            EventKind::Died => write!(f, "thread died, respawning."),
+            EventKind::RespawnFailed(kind) => write!(f, "failed to respawn: {:?}", kind),
+            EventKind::StartFailed => write!(f, "thread died while starting, not respawning."),
+        }
+    }
+}
+
+/// Where `Event`s go.
+///
+/// # Variants
+///
+/// - `Silent`: Dropped, the default.
+/// - `Callback`: Handed to a user-supplied closure.
+/// - `Log`: Sent to the `log` facade, target `rust_thread_pool`.
+#[derive(Default)]
+pub(crate) enum Sink {
+    #[default]
+    Silent,
+    Callback(Box<dyn Fn(&Event) + Send + Sync>),
+    #[cfg(feature = "log")]
+    Log,
+}
+
+impl Sink {
+    /// Sends `Event { worker, kind }` to the sink.
+    pub(crate) fn emit(&self, worker: usize, kind: EventKind) {
+        let event = Event { worker, kind };
+
+        match self {
+            Sink::Silent => {}
+            Sink::Callback(callback) => callback(&event),
+            #[cfg(feature = "log")]
+            Sink::Log => {
+                let level = match kind {
+                    EventKind::JobStarted => log::Level::Trace,
+                    EventKind::JobPanicked => log::Level::Warn,
+                    EventKind::Died | EventKind::RespawnFailed(_) | EventKind::StartFailed => {
+                        log::Level::Error
+                    }
+                    EventKind::Terminating | EventKind::ShuttingDown => log::Level::Debug,
+                };
+
+                log::log!(target: "rust_thread_pool", level, "{}", event);
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::cancel::CancellationToken;
+use crate::handle::{self, Completer};
+use crate::{rethrow_reported, JoinError, Priority, Shared, TaskHandle, ThreadPool};
+
+/// `Task::state`: Waiting for a wake-up.
+const IDLE: u8 = 0;
+/// `Task::state`: Queued to be polled.
+const SCHEDULED: u8 = 1;
+/// `Task::state`: Being polled by a worker.
+const RUNNING: u8 = 2;
+/// `Task::state`: Woken while being polled, so it is polled again.
+const NOTIFIED: u8 = 3;
+/// `Task::state`: Finished, the future is gone.
+const DONE: u8 = 4;
+
+/// A future spawned with `ThreadPool::spawn_future`.
+///
+/// # Members
+///
+/// - `future`: The future, until it is finished or dropped.
+/// - `completer`: Where its output goes.
+/// - `token`: For `TaskHandle::cancel()`.
+/// - `state`: One of the constants above, so a task is queued at most once
+///   however often it is woken.
+/// - `shared`: The pool to queue it on. `Weak`, so a waker kept around by
+///   some I/O source doesn't keep a dropped pool alive.
+///
+/// # Notes
+///
+/// Each poll is a regular job on the queue, so futures share the workers
+/// fairly with closures.
+struct Task<F: Future> {
+    future: Mutex<Option<Pin<Box<F>>>>,
+    completer: Mutex<Option<Completer<F::Output>>>,
+    token: CancellationToken,
+    state: AtomicU8,
+    shared: Weak<Shared>,
+}
+
+impl<F> Task<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    /// Queues the task to be polled.
+    ///
+    /// If the pool is gone or won't take it, the future is dropped and the
+    /// `TaskHandle` reports `JoinError::Disconnected`.
+    fn schedule(self: Arc<Self>) {
+        let shared = match self.shared.upgrade() {
+            Some(shared) => shared,
+            None => return self.finish(None),
+        };
+
+        let task = Arc::clone(&self);
+
+        if shared.push(Priority::Normal, move || task.run()).is_err() {
+            self.finish(None);
+        }
+    }
+
+    /// Polls the future once, on a worker.
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+
+        if !self.token.start() {
+            return self.finish(Some(Err(JoinError::Cancelled)));
+        }
+
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let poll = match future.as_mut() {
+            Some(future) => panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx))),
+            None => return,
+        };
+
+        drop(future);
+
+        match poll {
+            Ok(Poll::Ready(value)) => self.finish(Some(Ok(value))),
+            Ok(Poll::Pending) => {
+                let woken = self
+                    .state
+                    .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_err();
+
+                // Woken while we were polling, so go to the back of the queue.
+                if woken {
+                    self.state.store(SCHEDULED, Ordering::SeqCst);
+                    self.schedule();
+                }
+            }
+            Err(payload) => {
+                self.finish(Some(Err(JoinError::Panicked(payload))));
+                rethrow_reported("future panicked, payload sent to its TaskHandle");
+            }
+        }
+    }
+
+    /// Drops the future and hands `result` to the `TaskHandle`, or
+    /// `JoinError::Disconnected` if `None`.
+    fn finish(&self, result: Option<Result<F::Output, JoinError>>) {
+        self.state.store(DONE, Ordering::SeqCst);
+
+        let future = self
+            .future
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        // Out of the lock, dropping it may wake the task again.
+        drop(future);
+
+        let completer = self
+            .completer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        if let (Some(completer), Some(result)) = (completer, result) {
+            completer.complete(result);
+        }
+    }
+}
+
+impl<F> Wake for Task<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn wake(self: Arc<Self>) {
+        loop {
+            let (next, schedule) = match self.state.load(Ordering::SeqCst) {
+                IDLE => (SCHEDULED, true),
+                RUNNING => (NOTIFIED, false),
+                _ => return,
+            };
+
+            let current = if schedule { IDLE } else { RUNNING };
+
+            if self
+                .state
+                .compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                if schedule {
+                    self.schedule();
+                }
+
+                return;
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Runs `future` to completion on the worker threads.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(2);
+    ///
+    /// let inner = pool.submit(|| 20);
+    /// let outer = pool.spawn_future(async move { inner.await.unwrap() + 1 });
+    ///
+    /// assert_eq!(outer.join().unwrap(), 21);
+    /// ```
+    ///
+    /// # Notes
+    ///
+    /// Each poll runs as a job. When the future is woken, it is queued
+    /// again, like any other job, so it must not block the worker while
+    /// pending. The returned `TaskHandle` is a `Future` itself, so the
+    /// result can be awaited as well as joined.
+    ///
+    /// If the future panics, the payload goes to the handle as
+    /// `JoinError::Panicked`. A future woken after the pool shut down is
+    /// dropped, and the handle reports `JoinError::Disconnected`.
+    ///
+    /// `TaskHandle::cancel()` drops the future instead of polling it again.
+    pub fn spawn_future<F>(&self, future: F) -> TaskHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let token = CancellationToken::new(Arc::clone(&self.shared.shutdown));
+        let (completer, handle) = handle::channel(token.clone());
+
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            completer: Mutex::new(Some(completer)),
+            token,
+            state: AtomicU8::new(SCHEDULED),
+            shared: Arc::downgrade(&self.shared),
+        });
+
+        task.schedule();
+
+        handle
+    }
+}
+
+/// The future returned by `ThreadPool::run_async`.
+///
+/// # Notes
+///
+/// Dropping it before it resolves cancels the job if it hasn't started yet,
+/// the way dropping a future cancels it in async code. A job already running
+/// still runs to the end, its result is just dropped.
+pub struct RunAsync<T> {
+    handle: TaskHandle<T>,
+}
+
+impl<T> Future for RunAsync<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx)
+    }
+}
+
+impl<T> Drop for RunAsync<T> {
+    fn drop(&mut self) {
+        if !self.handle.is_finished() {
+            self.handle.cancel();
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Runs the blocking closure `f` on a worker, and resolves to its return
+    /// value once it is done.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    ///
+    /// async fn checksum(pool: &ThreadPool, data: Vec<u8>) -> u32 {
+    ///     // Doesn't hold up the executor while summing.
+    ///     pool.run_async(move || data.iter().map(|&b| b as u32).sum())
+    ///         .await
+    ///         .unwrap()
+    /// }
+    /// # let _ = checksum;
+    /// ```
+    ///
+    /// # Notes
+    ///
+    /// Only uses the `Waker` of the awaiting task, so it works under any
+    /// executor. Errors are those of `submit`, see `TaskHandle`.
+    pub fn run_async<F, T>(&self, f: F) -> RunAsync<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        RunAsync {
+            handle: self.submit(f),
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::latch::Latch;
+use crate::{rethrow_reported, GraphError, JoinError, Priority, Shared, ThreadPool};
+
+/// The closure of a node, getting the values of its dependencies.
+type NodeJob<T> = Box<dyn FnOnce(&[&T]) -> T + Send + 'static>;
+
+/// Identifies a node of a `TaskGraph`, returned by
+/// `TaskGraphBuilder::add_node()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// The position of the node, in the order the nodes were added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// What happened to a node of a `TaskGraph`.
+///
+/// # Variants
+///
+/// - `Done`: The node ran and returned this value.
+/// - `Failed`: The node panicked, or was dropped by a shutdown.
+/// - `Skipped`: The node never ran, because a dependency didn't succeed.
+#[derive(Debug)]
+pub enum NodeOutcome<T> {
+    Done(T),
+    Failed(JoinError),
+    Skipped,
+}
+
+/// Declares the nodes and dependencies of a `TaskGraph`.
+///
+/// # Usage
+///
+/// ```
+/// use rust_thread_pool::{TaskGraphBuilder, ThreadPool};
+///
+/// let pool = ThreadPool::new(4);
+/// let mut builder = TaskGraphBuilder::new();
+///
+/// let a = builder.add_node(|_| 2);
+/// let b = builder.add_node(|_| 3);
+/// let c = builder.add_node(|inputs: &[&u32]| inputs.iter().copied().product());
+///
+/// builder.add_dependency(c, a).add_dependency(c, b);
+///
+/// let report = pool.run_graph(builder.build().unwrap());
+///
+/// assert_eq!(report.value(c), Some(&6));
+/// assert!(report.failed().is_empty());
+/// ```
+///
+/// # Members
+///
+/// - `nodes`: The closure of every node, indexed by `NodeId`.
+/// - `edges`: `(node, dependency)` pairs, checked by `build()`.
+pub struct TaskGraphBuilder<T> {
+    nodes: Vec<NodeJob<T>>,
+    edges: Vec<(NodeId, NodeId)>,
+}
+
+impl<T> TaskGraphBuilder<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Creates an empty builder.
+    pub fn new() -> TaskGraphBuilder<T> {
+        TaskGraphBuilder {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// Adds a node running `f`.
+    ///
+    /// `f` gets the values of the dependencies of the node, in the order they
+    /// were added with `add_dependency`.
+    pub fn add_node<F>(&mut self, f: F) -> NodeId
+    where
+        F: FnOnce(&[&T]) -> T + Send + 'static,
+    {
+        self.nodes.push(Box::new(f));
+
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `node` wait for `dependency`, and get its value.
+    ///
+    /// Adding the same dependency twice does nothing.
+    pub fn add_dependency(&mut self, node: NodeId, dependency: NodeId) -> &mut TaskGraphBuilder<T> {
+        self.edges.push((node, dependency));
+        self
+    }
+
+    /// Checks the dependencies and creates the `TaskGraph`.
+    ///
+    /// # Errors
+    ///
+    /// - `GraphError::UnknownNode` if a dependency names a node that wasn't
+    ///   added to this builder.
+    /// - `GraphError::Cycle` if some nodes depend on each other, so they
+    ///   could never run.
+    ///
+    /// # Notes
+    ///
+    /// Cycles are found by sorting the nodes topologically: whatever is left
+    /// unsorted is on a cycle, or waits for one.
+    pub fn build(self) -> Result<TaskGraph<T>, GraphError> {
+        let count = self.nodes.len();
+        let mut deps = vec![Vec::new(); count];
+        let mut dependents = vec![Vec::new(); count];
+
+        for &(node, dependency) in &self.edges {
+            for &id in &[node, dependency] {
+                if id.0 >= count {
+                    return Err(GraphError::UnknownNode(id));
+                }
+            }
+
+            if !deps[node.0].contains(&dependency.0) {
+                deps[node.0].push(dependency.0);
+                dependents[dependency.0].push(node.0);
+            }
+        }
+
+        let mut waiting: Vec<usize> = deps.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..count).filter(|&node| waiting[node] == 0).collect();
+        let mut sorted = 0;
+
+        while let Some(node) = ready.pop() {
+            sorted += 1;
+
+            for &dependent in &dependents[node] {
+                waiting[dependent] -= 1;
+
+                if waiting[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+
+        if sorted < count {
+            let stuck = (0..count)
+                .filter(|&node| waiting[node] > 0)
+                .map(NodeId)
+                .collect();
+
+            return Err(GraphError::Cycle(stuck));
+        }
+
+        let nodes = self
+            .nodes
+            .into_iter()
+            .zip(deps)
+            .zip(dependents)
+            .map(|((job, deps), dependents)| GraphNode {
+                job,
+                deps,
+                dependents,
+            })
+            .collect();
+
+        Ok(TaskGraph { nodes })
+    }
+}
+
+impl<T> Default for TaskGraphBuilder<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn default() -> TaskGraphBuilder<T> {
+        TaskGraphBuilder::new()
+    }
+}
+
+/// A checked node of a `TaskGraph`.
+///
+/// # Members
+///
+/// - `job`: The closure.
+/// - `deps`: The nodes it gets the values of, in order.
+/// - `dependents`: The nodes waiting for it.
+struct GraphNode<T> {
+    job: NodeJob<T>,
+    deps: Vec<usize>,
+    dependents: Vec<usize>,
+}
+
+/// A set of jobs with dependencies and no cycles, created by
+/// `TaskGraphBuilder::build()` and run by `ThreadPool::run_graph()`.
+pub struct TaskGraph<T> {
+    nodes: Vec<GraphNode<T>>,
+}
+
+/// The outcome of every node of a `TaskGraph`, returned by
+/// `ThreadPool::run_graph()`.
+#[derive(Debug)]
+pub struct GraphReport<T> {
+    outcomes: Vec<NodeOutcome<T>>,
+}
+
+impl<T> GraphReport<T> {
+    /// What happened to `node`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` isn't part of the graph that was run.
+    pub fn outcome(&self, node: NodeId) -> &NodeOutcome<T> {
+        &self.outcomes[node.0]
+    }
+
+    /// The value of `node`, if it ran successfully.
+    pub fn value(&self, node: NodeId) -> Option<&T> {
+        match self.outcomes.get(node.0) {
+            Some(NodeOutcome::Done(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The nodes that panicked or were dropped.
+    pub fn failed(&self) -> Vec<NodeId> {
+        self.select(|outcome| matches!(outcome, NodeOutcome::Failed(_)))
+    }
+
+    /// The nodes that never ran, because a dependency didn't succeed.
+    pub fn skipped(&self) -> Vec<NodeId> {
+        self.select(|outcome| matches!(outcome, NodeOutcome::Skipped))
+    }
+
+    /// The outcome of every node, indexed by `NodeId::index()`.
+    pub fn into_outcomes(self) -> Vec<NodeOutcome<T>> {
+        self.outcomes
+    }
+
+    fn select(&self, predicate: impl Fn(&NodeOutcome<T>) -> bool) -> Vec<NodeId> {
+        self.outcomes
+            .iter()
+            .enumerate()
+            .filter(|(_, outcome)| predicate(outcome))
+            .map(|(index, _)| NodeId(index))
+            .collect()
+    }
+}
+
+/// A node while its graph is running.
+///
+/// # Members
+///
+/// - `job`: The closure, until it is dispatched or skipped.
+/// - `deps`, `dependents`: As in `GraphNode`.
+/// - `waiting`: How many `deps` are not resolved yet.
+/// - `outcome`: Set once the node is resolved. Values are shared with the
+///   dependents while they run.
+struct NodeState<T> {
+    job: Option<NodeJob<T>>,
+    deps: Vec<usize>,
+    dependents: Vec<usize>,
+    waiting: usize,
+    outcome: Option<NodeOutcome<Arc<T>>>,
+}
+
+/// A node ready to be dispatched: its index, closure and input values.
+type Ready<T> = (usize, NodeJob<T>, Vec<Arc<T>>);
+
+/// What the nodes of a running graph share.
+///
+/// # Members
+///
+/// - `shared`: The pool the nodes are dispatched to.
+/// - `nodes`: The state of every node.
+/// - `unresolved`: How many nodes have no outcome yet.
+/// - `done`: Set once `unresolved` reaches 0.
+struct GraphRun<T> {
+    shared: Arc<Shared>,
+    nodes: Mutex<Vec<NodeState<T>>>,
+    unresolved: AtomicUsize,
+    done: Latch,
+}
+
+impl<T> GraphRun<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Records the outcome of `index`, and dispatches or skips the nodes
+    /// that were only waiting for it.
+    fn resolve(self: &Arc<GraphRun<T>>, index: usize, outcome: NodeOutcome<Arc<T>>) {
+        let mut ready = Vec::new();
+        let mut skipped_jobs = Vec::new();
+        let mut resolved = vec![(index, outcome)];
+        let mut resolved_count = 0;
+
+        {
+            let mut nodes = self.nodes.lock().unwrap_or_else(PoisonError::into_inner);
+
+            while let Some((index, outcome)) = resolved.pop() {
+                resolved_count += 1;
+                nodes[index].outcome = Some(outcome);
+
+                for dependent in mem::take(&mut nodes[index].dependents) {
+                    nodes[dependent].waiting -= 1;
+
+                    if nodes[dependent].waiting > 0 {
+                        continue;
+                    }
+
+                    let inputs: Option<Vec<Arc<T>>> = nodes[dependent]
+                        .deps
+                        .iter()
+                        .map(|&dep| match &nodes[dep].outcome {
+                            Some(NodeOutcome::Done(value)) => Some(Arc::clone(value)),
+                            _ => None,
+                        })
+                        .collect();
+
+                    let job = nodes[dependent].job.take();
+
+                    match (job, inputs) {
+                        (Some(job), Some(inputs)) => ready.push((dependent, job, inputs)),
+                        (job, _) => {
+                            skipped_jobs.extend(job);
+                            resolved.push((dependent, NodeOutcome::Skipped));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Dropped outside the lock, in case the captures do anything.
+        drop(skipped_jobs);
+
+        if self.unresolved.fetch_sub(resolved_count, Ordering::SeqCst) == resolved_count {
+            self.done.set();
+        }
+
+        for node in ready {
+            self.dispatch(node);
+        }
+    }
+
+    /// Queues a node on the pool.
+    ///
+    /// # Notes
+    ///
+    /// If the pool rejects the job, or drops it on `shutdown_now()`, the
+    /// `NodeGuard` inside resolves the node as `JoinError::Disconnected`.
+    fn dispatch(self: &Arc<GraphRun<T>>, (index, job, inputs): Ready<T>) {
+        let guard = NodeGuard {
+            run: Arc::clone(self),
+            index,
+            inputs,
+            resolved: false,
+        };
+
+        let _ = self.shared.push(Priority::Normal, move || guard.run(job));
+    }
+}
+
+/// Carries a dispatched node and its inputs, and makes sure it gets resolved.
+///
+/// # Notes
+///
+/// The inputs are dropped before resolving, so once the whole graph is
+/// resolved every value has a single owner left.
+struct NodeGuard<T>
+where
+    T: Send + Sync + 'static,
+{
+    run: Arc<GraphRun<T>>,
+    index: usize,
+    inputs: Vec<Arc<T>>,
+    resolved: bool,
+}
+
+impl<T> NodeGuard<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Runs the node on a worker.
+    fn run(mut self, job: NodeJob<T>) {
+        let result = {
+            let values: Vec<&T> = self.inputs.iter().map(|value| &**value).collect();
+
+            panic::catch_unwind(AssertUnwindSafe(|| job(&values)))
+        };
+
+        match result {
+            Ok(value) => self.resolve(NodeOutcome::Done(Arc::new(value))),
+            Err(payload) => {
+                self.resolve(NodeOutcome::Failed(JoinError::Panicked(payload)));
+                rethrow_reported("graph node panicked, payload sent to its report");
+            }
+        }
+    }
+
+    fn resolve(&mut self, outcome: NodeOutcome<Arc<T>>) {
+        self.resolved = true;
+        self.inputs.clear();
+        self.run.resolve(self.index, outcome);
+    }
+}
+
+impl<T> Drop for NodeGuard<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn drop(&mut self) {
+        if !self.resolved {
+            self.resolve(NodeOutcome::Failed(JoinError::Disconnected));
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Runs every node of `graph` on the pool, and blocks until all of them
+    /// are resolved.
+    ///
+    /// # Steps
+    ///
+    /// 1. Dispatch the nodes without dependencies.
+    /// 2. Whenever a node finishes, dispatch each dependent whose
+    ///    dependencies are now all done, passing their values along. If one
+    ///    of them failed or was skipped, skip the dependent instead.
+    /// 3. Once every node is resolved, collect the outcomes.
+    ///
+    /// # Notes
+    ///
+    /// A panicking node is `Failed` and counted as a panicked job, it doesn't
+    /// stop the independent parts of the graph.
+    ///
+    /// Called from one of this pool's workers, it runs queued jobs while
+    /// waiting, like `join`.
+    pub fn run_graph<T>(&self, graph: TaskGraph<T>) -> GraphReport<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        let count = graph.nodes.len();
+        let mut roots = Vec::new();
+
+        let nodes = graph
+            .nodes
+            .into_iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let mut job = Some(node.job);
+
+                if node.deps.is_empty() {
+                    roots.push((index, job.take().unwrap(), Vec::new()));
+                }
+
+                NodeState {
+                    job,
+                    waiting: node.deps.len(),
+                    deps: node.deps,
+                    dependents: node.dependents,
+                    outcome: None,
+                }
+            })
+            .collect();
+
+        let run = Arc::new(GraphRun {
+            shared: Arc::clone(&self.shared),
+            nodes: Mutex::new(nodes),
+            unresolved: AtomicUsize::new(count),
+            done: Latch::new(),
+        });
+
+        if count == 0 {
+            run.done.set();
+        }
+
+        for root in roots {
+            run.dispatch(root);
+        }
+
+        self.wait_helping(&run.done);
+
+        let nodes = mem::take(&mut *run.nodes.lock().unwrap_or_else(PoisonError::into_inner));
+
+        let outcomes = nodes
+            .into_iter()
+            .map(|node| match node.outcome {
+                Some(NodeOutcome::Done(value)) => NodeOutcome::Done(
+                    Arc::try_unwrap(value)
+                        .unwrap_or_else(|_| unreachable!("node value still shared")),
+                ),
+                Some(NodeOutcome::Failed(e)) => NodeOutcome::Failed(e),
+                Some(NodeOutcome::Skipped) | None => NodeOutcome::Skipped,
+            })
+            .collect();
+
+        GraphReport { outcomes }
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use crate::handle;
+use crate::{CancellationToken, JoinError, TaskHandle, ThreadPool};
+
+/// What the jobs of a `TaskGroup` share, behind `GroupState::inner`.
+///
+/// # Members
+///
+/// - `failed`: Set once a job panicked.
+/// - `fail_fast`: See `TaskGroup::fail_fast()`.
+/// - `tokens`: The tokens of every job, recorded even without `fail_fast`,
+///   so turning it on later still reaches the jobs spawned before.
+struct GroupInner {
+    failed: bool,
+    fail_fast: bool,
+    tokens: Vec<CancellationToken>,
+}
+
+struct GroupState {
+    inner: Mutex<GroupInner>,
+}
+
+impl GroupState {
+    fn lock(&self) -> MutexGuard<'_, GroupInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Marks the group as failed, and cancels every job in fail-fast mode.
+    fn fail(&self) {
+        let mut inner = self.lock();
+
+        inner.failed = true;
+        inner.cancel_all();
+    }
+
+    /// Records `token`, or returns `false` if the group already failed fast.
+    ///
+    /// # Notes
+    ///
+    /// Under the lock, so `fail` either sees the token or we see `failed`.
+    fn add(&self, token: &CancellationToken) -> bool {
+        let mut inner = self.lock();
+
+        if inner.failed && inner.fail_fast {
+            return false;
+        }
+
+        inner.tokens.push(token.clone());
+
+        true
+    }
+
+    fn set_fail_fast(&self, fail_fast: bool) {
+        let mut inner = self.lock();
+
+        inner.fail_fast = fail_fast;
+        inner.cancel_all();
+    }
+}
+
+impl GroupInner {
+    /// Cancels every job, if the group failed in fail-fast mode.
+    fn cancel_all(&self) {
+        if self.failed && self.fail_fast {
+            for token in &self.tokens {
+                token.cancel();
+            }
+        }
+    }
+}
+
+/// Fails the group if dropped while its job is panicking.
+struct FailGuard(Arc<GroupState>);
+
+impl Drop for FailGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.fail();
+        }
+    }
+}
+
+/// A batch of jobs waited on together, created by `ThreadPool::task_group()`.
+///
+/// # Usage
+///
+/// ```
+/// use rust_thread_pool::ThreadPool;
+///
+/// let pool = ThreadPool::new(4);
+/// let mut group = pool.task_group();
+///
+/// for i in 0..4 {
+///     group.spawn(move || i * i);
+/// }
+///
+/// let squares: Vec<_> = group.wait().into_iter().map(Result::unwrap).collect();
+///
+/// assert_eq!(squares, vec![0, 1, 4, 9]);
+/// ```
+///
+/// # Notes
+///
+/// A job fails by panicking. In fail-fast mode, the first failure cancels
+/// the rest of the group: jobs not started yet report `JoinError::Cancelled`,
+/// and running ones see their `CancellationToken` cancelled. So do the jobs
+/// spawned afterwards, which aren't queued at all.
+pub struct TaskGroup<'pool, T> {
+    pool: &'pool ThreadPool,
+    handles: Vec<TaskHandle<T>>,
+    state: Arc<GroupState>,
+}
+
+impl<'pool, T> TaskGroup<'pool, T>
+where
+    T: Send + 'static,
+{
+    /// Cancels the rest of the group once a job fails. Off by default.
+    ///
+    /// Covers the jobs spawned before too. If one of them already failed,
+    /// the others are cancelled right away.
+    pub fn fail_fast(self, fail_fast: bool) -> TaskGroup<'pool, T> {
+        self.state.set_fail_fast(fail_fast);
+        self
+    }
+
+    /// Runs `f` in the pool as part of the group.
+    pub fn spawn<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.spawn_cancellable(move |_| f());
+    }
+
+    /// Like `spawn`, but `f` gets a `CancellationToken`, see
+    /// `ThreadPool::submit_cancellable()`.
+    pub fn spawn_cancellable<F>(&mut self, f: F)
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+    {
+        let token = CancellationToken::new(Arc::clone(&self.pool.shared.shutdown));
+
+        if !self.state.add(&token) {
+            let (completer, handle) = handle::channel(token);
+
+            completer.complete(Err(JoinError::Cancelled));
+            self.handles.push(handle);
+
+            return;
+        }
+
+        let guard = FailGuard(Arc::clone(&self.state));
+
+        let handle = self.pool.submit_with_token(token, move |token| {
+            let _guard = guard;
+
+            f(token)
+        });
+
+        self.handles.push(handle);
+    }
+
+    /// The number of jobs spawned so far.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Whether no job was spawned yet.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Blocks until every job is finished, and returns their results in the
+    /// order they were spawned.
+    pub fn wait(self) -> Vec<Result<T, JoinError>> {
+        self.handles.into_iter().map(TaskHandle::join).collect()
+    }
+}
+
+impl ThreadPool {
+    /// Creates an empty `TaskGroup` running on this pool.
+    pub fn task_group<T>(&self) -> TaskGroup<'_, T>
+    where
+        T: Send + 'static,
+    {
+        TaskGroup {
+            pool: self,
+            handles: Vec::new(),
+            state: Arc::new(GroupState {
+                inner: Mutex::new(GroupInner {
+                    failed: false,
+                    fail_fast: false,
+                    tokens: Vec::new(),
+                }),
+            }),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::error::JoinError;
+
+/// State of a `Slot`.
+///
+/// # Notes
+///
+/// `Taken` is kept apart from `Pending` so a second `try_join` after the value
+/// was handed out reports `Disconnected` instead of waiting forever.
+enum State<T> {
+    Pending,
+    Ready(Result<T, JoinError>),
+    Taken,
+}
+
+/// The place where a job leaves its result.
+///
+/// # Members
+///
+/// - `state`: What the job produced so far.
+/// - `ready`: Notified once `state` leaves `Pending`.
+/// - `waker`: Woken once `state` leaves `Pending`, if the handle is awaited.
+///
+/// # Notes
+///
+/// `poll` stores the waker while holding `state`, and `fill` only takes it
+/// after updating `state`, so a wake-up can't fall in between.
+struct Slot<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<T> Slot<T> {
+    /// Stores `result` and wakes up the waiting handle.
+    ///
+    /// Only the first call has any effect.
+    fn fill(&self, result: Result<T, JoinError>) {
+        let mut state = self.state.lock().unwrap();
+
+        if !matches!(*state, State::Pending) {
+            return;
+        }
+
+        *state = State::Ready(result);
+        self.ready.notify_all();
+
+        drop(state);
+
+        let waker = self
+            .waker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Creates a connected `Completer` / `TaskHandle` pair, like `mpsc::channel()`.
+///
+/// `token` is the one of the job, for `TaskHandle::cancel()`.
+pub(crate) fn channel<T>(token: CancellationToken) -> (Completer<T>, TaskHandle<T>) {
+    let slot = Arc::new(Slot {
+        state: Mutex::new(State::Pending),
+        ready: Condvar::new(),
+        waker: Mutex::new(None),
+    });
+
+    let completer = Completer {
+        slot: Some(Arc::clone(&slot)),
+    };
+
+    (completer, TaskHandle { slot, token })
+}
+
+/// The sending side of a `TaskHandle`, moved into the job.
+///
+/// # Notes
+///
+/// Dropping it without calling `complete` (e.g. the job never ran) fills the
+/// slot with `JoinError::Disconnected`, so the handle never waits forever.
+pub(crate) struct Completer<T> {
+    slot: Option<Arc<Slot<T>>>,
+}
+
+impl<T> Completer<T> {
+    /// Hands `result` over to the `TaskHandle`.
+    pub(crate) fn complete(mut self, result: Result<T, JoinError>) {
+        if let Some(slot) = self.slot.take() {
+            slot.fill(result);
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.fill(Err(JoinError::Disconnected));
+        }
+    }
+}
+
+/// Handle to a job started with `ThreadPool::submit`.
+///
+/// # Notes
+///
+/// The value can be taken out only once. Later calls return
+/// `JoinError::Disconnected`.
+///
+/// It is also a `Future` of the same result, so async code can await a job
+/// instead of blocking on `join()`.
+pub struct TaskHandle<T> {
+    slot: Arc<Slot<T>>,
+    token: CancellationToken,
+}
+
+impl<T> TaskHandle<T> {
+    /// Blocks until the job finishes and returns its value.
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut state = self.slot.state.lock().unwrap();
+
+        while let State::Pending = *state {
+            state = self.slot.ready.wait(state).unwrap();
+        }
+
+        take(&mut state)
+    }
+
+    /// Returns the value if the job has already finished.
+    ///
+    /// Returns `JoinError::Pending` otherwise, without blocking.
+    pub fn try_join(&self) -> Result<T, JoinError> {
+        let mut state = self.slot.state.lock().unwrap();
+
+        if let State::Pending = *state {
+            return Err(JoinError::Pending);
+        }
+
+        take(&mut state)
+    }
+
+    /// Blocks for at most `timeout` waiting for the job.
+    ///
+    /// Returns `JoinError::Timeout` if the job is still running afterwards.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<T, JoinError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.slot.state.lock().unwrap();
+
+        while let State::Pending = *state {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(JoinError::Timeout);
+            }
+
+            state = self
+                .slot
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        take(&mut state)
+    }
+
+    /// Cancels the job.
+    ///
+    /// Returns `true` if it hadn't started yet. It then never runs, and the
+    /// handle reports `JoinError::Cancelled` right away. The closure itself is
+    /// dropped once a worker gets to it in the queue.
+    ///
+    /// Returns `false` if it is already running or finished. A job started
+    /// with `ThreadPool::submit_cancellable` sees its token cancelled and may
+    /// stop early, a future from `ThreadPool::spawn_future` is dropped when
+    /// it is next woken, and any other job just runs to the end.
+    pub fn cancel(&self) -> bool {
+        let cancelled = self.token.cancel();
+
+        if cancelled {
+            self.slot.fill(Err(JoinError::Cancelled));
+        }
+
+        cancelled
+    }
+
+    /// Whether the job has finished (or was dropped).
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.slot.state.lock().unwrap(), State::Pending)
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+
+        if let State::Pending = *state {
+            let mut waker = self
+                .slot
+                .waker
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            match &*waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                _ => *waker = Some(cx.waker().clone()),
+            }
+
+            return Poll::Pending;
+        }
+
+        Poll::Ready(take(&mut state))
+    }
+}
+
+/// Moves the value out of a non-pending `state`.
+fn take<T>(state: &mut State<T>) -> Result<T, JoinError> {
+    match mem::replace(state, State::Taken) {
+        State::Ready(result) => result,
+        State::Taken => Err(JoinError::Disconnected),
+        State::Pending => unreachable!("take() called on a pending slot"),
+    }
+}
@@ -0,0 +1,139 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+use crate::latch::Latch;
+use crate::{rethrow_reported, Job, ThreadPool};
+
+/// The half of a `join` that is offered to the pool.
+///
+/// # Members
+///
+/// - `job`: The closure, until a worker or the caller takes it.
+/// - `result`: What it returned, or its panic payload.
+/// - `done`: Set once `result` is filled in.
+struct JoinState<B, RB> {
+    job: Mutex<Option<B>>,
+    result: Mutex<Option<thread::Result<RB>>>,
+    done: Latch,
+}
+
+impl<B, RB> JoinState<B, RB>
+where
+    B: FnOnce() -> RB,
+{
+    /// Takes the closure out, if nobody else did yet.
+    fn take(&self) -> Option<B> {
+        self.job
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Runs the closure on a worker, unless the caller got it back first.
+    fn run(&self) {
+        let job = match self.take() {
+            Some(job) => job,
+            None => return,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        let panicked = result.is_err();
+
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.done.set();
+
+        if panicked {
+            rethrow_reported("joined job panicked, payload sent to its caller");
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Runs `oper_a` and `oper_b`, potentially in parallel, and returns both
+    /// results.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    ///
+    /// fn sum(pool: &ThreadPool, numbers: &[u64]) -> u64 {
+    ///     if numbers.len() <= 1024 {
+    ///         return numbers.iter().sum();
+    ///     }
+    ///
+    ///     let (left, right) = numbers.split_at(numbers.len() / 2);
+    ///     let (a, b) = pool.join(|| sum(pool, left), || sum(pool, right));
+    ///
+    ///     a + b
+    /// }
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers: Vec<u64> = (1..=100_000).collect();
+    ///
+    /// assert_eq!(sum(&pool, &numbers), 5_000_050_000);
+    /// ```
+    ///
+    /// # Steps
+    ///
+    /// 1. Queue `oper_b`, so an idle worker can pick it up.
+    /// 2. Run `oper_a` on the calling thread.
+    /// 3. If no worker took `oper_b` yet, run it on the calling thread too.
+    ///    Otherwise wait for it. A worker of this pool keeps running queued
+    ///    jobs meanwhile, so nested `join`s can't starve the pool.
+    ///
+    /// # Notes
+    ///
+    /// Both closures may borrow from the caller's stack, like in `scope()`.
+    /// `oper_a` always runs on the calling thread, so it needn't be `Send`.
+    ///
+    /// # Panics
+    ///
+    /// Panics again with the payload of `oper_a`, or else of `oper_b`, after
+    /// both are finished.
+    pub fn join<A, B, RA, RB>(&self, oper_a: A, oper_b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA,
+        B: FnOnce() -> RB + Send,
+        RB: Send,
+    {
+        let state = Arc::new(JoinState {
+            job: Mutex::new(Some(oper_b)),
+            result: Mutex::new(None),
+            done: Latch::new(),
+        });
+
+        let job_state = Arc::clone(&state);
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || job_state.run());
+
+        // SAFETY: Before returning, `join` either takes `oper_b` back or waits
+        // on `done`, which `run` sets after its last use of `oper_b` and its
+        // result. A queued job outliving `join` only finds `None` in both.
+        let job: Job = unsafe { mem::transmute(job) };
+
+        let _ = self.try_run(job);
+
+        let result_a = panic::catch_unwind(AssertUnwindSafe(oper_a));
+
+        let result_b = match state.take() {
+            Some(oper_b) => panic::catch_unwind(AssertUnwindSafe(oper_b)),
+            None => {
+                self.wait_helping(&state.done);
+
+                state
+                    .result
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take()
+                    .expect("joined job finished without a result")
+            }
+        };
+
+        match (result_a, result_b) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+        }
+    }
+}
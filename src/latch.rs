@@ -0,0 +1,53 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+/// A one-shot signal, set once by one thread and waited on by another.
+///
+/// # Members
+///
+/// - `set`: Whether `set()` was called.
+/// - `cond`: Notified when `set` becomes `true`.
+pub(crate) struct Latch {
+    set: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Latch {
+    pub(crate) fn new() -> Latch {
+        Latch {
+            set: Mutex::new(false),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Sets the latch and wakes up everyone waiting on it.
+    pub(crate) fn set(&self) {
+        *self.set.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.cond.notify_all();
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        *self.set.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks until the latch is set.
+    pub(crate) fn wait(&self) {
+        let mut set = self.set.lock().unwrap_or_else(PoisonError::into_inner);
+
+        while !*set {
+            set = self.cond.wait(set).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Blocks until the latch is set, or for at most `timeout`.
+    pub(crate) fn wait_timeout(&self, timeout: Duration) {
+        let set = self.set.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !*set {
+            let _ = self
+                .cond
+                .wait_timeout(set, timeout)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
@@ -1,35 +1,315 @@
-use std::sync::mpsc;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::sync::{Condvar, Mutex, MutexGuard};
 use std::thread;
+use std::time::{Duration, Instant};
+
+mod builder;
+mod cancel;
+mod error;
+mod event;
+mod future;
+mod graph;
+mod group;
+mod handle;
+mod join;
+mod latch;
+mod par;
+mod priority;
+mod queue;
+mod scope;
+mod stats;
+mod timer;
+
+pub use builder::ThreadPoolBuilder;
+pub use cancel::CancellationToken;
+pub use error::{GraphError, JoinError, PoolBuildError, SubmitError};
+pub use event::{Event, EventKind};
+pub use future::RunAsync;
+pub use graph::{GraphReport, NodeId, NodeOutcome, TaskGraph, TaskGraphBuilder};
+pub use group::TaskGroup;
+pub use handle::TaskHandle;
+pub use priority::Priority;
+pub use queue::OverflowPolicy;
+pub use scope::Scope;
+pub use stats::PoolStats;
+pub use timer::ScheduledHandle;
+
+use builder::{ThreadConfig, DEFAULT_KEEP_ALIVE};
+use latch::Latch;
+use queue::{JobQueue, QueueConfig, Queued};
+use stats::Counters;
+use timer::Timer;
 
 /// Type `Job`.
 ///
 /// # Notes
 ///
 /// Just a wrapper. Who likes to write the full type name so many times,uh?
-type Job = Box<dyn FnOnce() + Send + 'static>;
+///
+/// Public because `ThreadPool::shutdown_now()` hands unstarted jobs back.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Panics again once a job's panic payload was handed to whoever waits for it.
+///
+/// # Notes
+///
+/// Lets the `Worker` see the panic too, for `PoolStats` and `Event`s, without
+/// running the panic hook twice. `what` says where the real payload went.
+fn rethrow_reported(what: &'static str) -> ! {
+    panic::resume_unwind(Box::new(what))
+}
 
 /// Message
 ///
 /// # Usage
 ///
-/// `NewJob` has a member `Job`, to run it in the `Worker` thread.
+/// `NewJob` has a member `Queued`, to run its `Job` in the `Worker` thread.
 ///
-/// `Terminate` makes the thread stop.
+/// `Terminate` makes the thread stop. `JobQueue::pop()` returns it once the
+/// queue is closed and drained.
 enum Message {
-    NewJob(Job),
+    NewJob(Queued),
     Terminate,
 }
 
+/// State shared by the `ThreadPool` and all of its `Worker`s.
+///
+/// # Members
+///
+/// - `queue`: The jobs waiting for a `Worker`.
+/// - `config`: Thread settings from `ThreadPoolBuilder`.
+/// - `counters`: Statistics, see `PoolStats`.
+/// - `live_workers`: Worker threads currently running their loop.
+/// - `exit_lock`, `exited`: Notified whenever a worker thread exits.
+/// - `timer`: Holds the jobs scheduled for later.
+/// - `shutdown`: Set on shutdown, cancels every `CancellationToken`.
+/// - `workers`: Contains the `Worker` objects.
+struct Shared {
+    queue: JobQueue,
+    config: ThreadConfig,
+    workers: Mutex<Workers>,
+    counters: Counters,
+    live_workers: AtomicUsize,
+    exit_lock: Mutex<()>,
+    exited: Condvar,
+    timer: Timer,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Shared {
+    /// Blocks until every worker thread has exited, or `deadline` passes.
+    ///
+    /// Returns `true` if they all exited.
+    fn wait_exited(&self, deadline: Instant) -> bool {
+        let mut guard = self
+            .exit_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while self.live_workers.load(Ordering::SeqCst) > 0 {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return false;
+            }
+
+            guard = self
+                .exited
+                .wait_timeout(guard, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        true
+    }
+
+    /// Runs `job`, catching a panic and counting it in `Counters`.
+    ///
+    /// Returns `true` if the job panicked.
+    fn run_job(&self, job: Job) -> bool {
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        let panicked = result.is_err();
+
+        self.counters.record_run(start.elapsed(), panicked);
+
+        // Only now, as dropping a panic payload may panic again.
+        drop(result);
+
+        panicked
+    }
+
+    /// Queues `f`, see `ThreadPool::try_run_with_priority`.
+    fn push<F>(self: &Arc<Shared>, priority: Priority, f: F) -> Result<(), SubmitError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // After a shutdown, every worker is gone, but that is no surprise.
+        if self.live_workers.load(Ordering::SeqCst) == 0 && !self.queue.is_closed() {
+            return Err(SubmitError::Closed(f));
+        }
+
+        let result = match self.queue.push(priority, f) {
+            Err(SubmitError::Full(f)) if self.queue.policy() == OverflowPolicy::CallerRuns => {
+                self.run_job(Box::new(f));
+
+                return Ok(());
+            }
+            result => result,
+        };
+
+        if result.is_ok() {
+            self.grow();
+        }
+
+        result
+    }
+
+    /// Spawns an extra `Worker` if jobs are waiting and nobody is idle,
+    /// up to `ThreadConfig::max_threads`.
+    ///
+    /// # Notes
+    ///
+    /// A failed spawn is ignored, the job waits for a busy worker instead.
+    fn grow(self: &Arc<Shared>) {
+        let max_threads = match self.config.max_threads {
+            Some(max_threads) => max_threads,
+            None => return,
+        };
+
+        if !self.queue.needs_worker() {
+            return;
+        }
+
+        let mut workers = self.workers();
+
+        if workers.active.len() + workers.extra.len() >= max_threads || self.queue.is_closed() {
+            return;
+        }
+
+        workers.reap();
+
+        let keep_alive = self.config.keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE);
+
+        if let Ok(worker) = workers.spawn(self, Some(keep_alive)) {
+            workers.extra.push(worker);
+        }
+    }
+
+    /// Locks the `Workers`.
+    fn workers(&self) -> MutexGuard<'_, Workers> {
+        self.workers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs a job taken off the queue on Worker `id`, reporting it to the
+    /// event sink.
+    ///
+    /// `helping` is set when called from `ThreadPool::wait_helping`, where
+    /// the worker already counts as active.
+    fn execute(&self, id: usize, queued: Queued, helping: bool) {
+        self.counters.record_wait(queued.since.elapsed());
+
+        let mut guard = JobGuard::new(self, !helping);
+
+        self.config.sink.emit(id, EventKind::JobStarted);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(queued.job));
+
+        guard.panicked = result.is_err();
+
+        // May panic again, if the payload's `Drop` does.
+        drop(result);
+
+        if guard.panicked {
+            self.config.sink.emit(id, EventKind::JobPanicked);
+        }
+    }
+}
+
+/// Keeps the books of a job run by `Shared::execute`.
+///
+/// # Members
+///
+/// - `start`: When the job started, for `PoolStats::total_run_time`.
+/// - `active`: Whether the job made its worker count as active.
+/// - `panicked`: Cleared once the job returned normally.
+///
+/// # Notes
+///
+/// Done on drop, so it also happens when the worker dies halfway, e.g. from a
+/// panic payload whose `Drop` panics or a panicking `on_event`. Otherwise
+/// `JobQueue::in_flight` would never drop back and `wait_idle` would hang,
+/// and `PoolStats` would show a busy worker forever.
+struct JobGuard<'a> {
+    shared: &'a Shared,
+    start: Instant,
+    active: bool,
+    panicked: bool,
+}
+
+impl<'a> JobGuard<'a> {
+    fn new(shared: &'a Shared, active: bool) -> JobGuard<'a> {
+        if active {
+            shared
+                .counters
+                .active_workers
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        JobGuard {
+            shared,
+            start: Instant::now(),
+            active,
+            panicked: true,
+        }
+    }
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        let counters = &self.shared.counters;
+
+        if self.active {
+            counters.active_workers.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        counters.record_run(self.start.elapsed(), self.panicked);
+
+        // Last, so `wait_idle` returns with the stats up to date.
+        self.shared.queue.finish(1);
+    }
+}
+
 /// Worker
 ///
 /// # Notes
 ///
 /// `id` is just a number in our library, not the real pid.
+///
+/// `control` is shared with the thread itself, see `Control`.
 struct Worker {
     id: usize,
-    handle: Option<thread::JoinHandle<()>>,
+    control: Arc<Control>,
+}
+
+/// What a `Worker` shares with its thread.
+///
+/// # Members
+///
+/// - `handle`: The `JoinHandle`. Shared so a replacement thread spawned by
+///   `Sentinel` can put its own handle in place.
+/// - `retire`: Tells the thread to exit after its current job, see
+///   `ThreadPool::set_num_threads()`.
+/// - `keep_alive`: For a worker spawned on demand, how long it waits for a
+///   job before exiting, see `ThreadPoolBuilder::max_threads()`.
+struct Control {
+    handle: Mutex<Option<thread::JoinHandle<()>>>,
+    retire: AtomicBool,
+    keep_alive: Option<Duration>,
 }
 
 impl Worker {
@@ -37,28 +317,207 @@ impl Worker {
     ///
     /// # Parameters
     ///
-    /// - `id`: The given id in `ThreadPool::new()`.
-    /// - `receiver`:The receiver side constructed in `ThreadPool::new()`.
-    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let handle = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
+    /// - `id`: A unique id, see `Workers::next_id`.
+    /// - `shared`: The `Shared` state constructed in `ThreadPool::new()`.
+    /// - `keep_alive`: See `Control`. `None` for the core workers.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `io::Error` from `thread::Builder::spawn` if the OS refuses
+    /// to create the thread.
+    pub fn new(id: usize, shared: Arc<Shared>, keep_alive: Option<Duration>) -> io::Result<Worker> {
+        let control = Arc::new(Control {
+            handle: Mutex::new(None),
+            retire: AtomicBool::new(false),
+            keep_alive,
+        });
+
+        Worker::spawn(id, shared, Arc::clone(&control))?;
+
+        Ok(Worker { id, control })
+    }
+
+    /// Spawns the thread of Worker `id` and stores its `JoinHandle` in `control`.
+    ///
+    /// # Notes
+    ///
+    /// Every job runs under `catch_unwind`, so a panicking job is counted and
+    /// the worker keeps serving instead of dying with it. If the thread dies
+    /// anyway, its `Sentinel` calls this again with the same `id`.
+    ///
+    /// The handle stays locked until it is stored, so a thread dying right
+    /// away can't have its replacement overwritten.
+    fn spawn(id: usize, shared: Arc<Shared>, control: Arc<Control>) -> io::Result<()> {
+        let mut guard = control
+            .handle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let sentinel = Sentinel {
+            id,
+            shared: Arc::clone(&shared),
+            control: Arc::clone(&control),
+            serving: false,
+        };
+        let thread_shared = Arc::clone(&shared);
+        let thread_control = Arc::clone(&control);
+
+        let handle = shared.config.thread_builder(id).spawn(move || {
+            let mut sentinel = sentinel;
+            let shared = thread_shared;
+            let control = thread_control;
+
+            if let Some(on_thread_start) = &shared.config.on_thread_start {
+                on_thread_start(id);
+            }
+
+            let local = shared.queue.register(id);
+
+            sentinel.serving = true;
+
+            loop {
+                match shared
+                    .queue
+                    .pop(local.as_deref(), &control.retire, control.keep_alive)
+                {
+                    Message::NewJob(queued) => shared.execute(id, queued, false),
+                    Message::Terminate => {
+                        shared.config.sink.emit(id, EventKind::Terminating);
+                        break;
+                    }
+                }
+            }
+
+            // Hand whatever is left in our deque to the others.
+            shared.queue.unregister(id);
+            shared.workers().release(id);
+        })?;
+
+        shared.live_workers.fetch_add(1, Ordering::SeqCst);
+        *guard = Some(handle);
+
+        Ok(())
+    }
+}
+
+/// Sentinel
+///
+/// # Notes
+///
+/// Lives on the stack of a `Worker` thread, runs the `on_thread_stop` hook and
+/// keeps `Shared::live_workers` in sync. When it is dropped while the thread
+/// is unwinding, the thread is dying unexpectedly, so it spawns a replacement
+/// with the same `id`, keeping the pool at its configured size.
+///
+/// `serving` is set once the thread is about to take its first job. A thread
+/// dying before that (e.g. `on_thread_start` panicked) is not respawned, as
+/// its replacement would most likely die the same way, over and over.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+    control: Arc<Control>,
+    serving: bool,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if let Some(on_thread_stop) = &self.shared.config.on_thread_stop {
+            on_thread_stop(self.id);
+        }
+
+        if thread::panicking() && !self.serving {
+            self.shared
+                .config
+                .sink
+                .emit(self.id, EventKind::StartFailed);
+        }
+
+        if thread::panicking() && self.serving {
+            self.shared.config.sink.emit(self.id, EventKind::Died);
 
-            match job {
-                Message::NewJob(job) => {
-                    println!("Worker #{}: get job, running.", id);
-                    job();
+            match Worker::spawn(self.id, Arc::clone(&self.shared), Arc::clone(&self.control)) {
+                Ok(()) => {
+                    self.shared
+                        .counters
+                        .respawned_workers
+                        .fetch_add(1, Ordering::Relaxed);
                 }
-                Message::Terminate => {
-                    println!("Worker #{}:get singal terminate, terminating.", id);
-                    break;
+                Err(e) => {
+                    let kind = EventKind::RespawnFailed(e.kind());
+
+                    self.shared.config.sink.emit(self.id, kind);
                 }
             }
-        });
+        }
 
-        return Worker {
-            handle: Some(handle),
-            id,
-        };
+        // After the respawn, so a 1-thread pool never looks closed in between.
+        self.shared.live_workers.fetch_sub(1, Ordering::SeqCst);
+
+        let _guard = self
+            .shared
+            .exit_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        self.shared.exited.notify_all();
+    }
+}
+
+/// The `Worker`s of a `ThreadPool`.
+///
+/// # Members
+///
+/// - `active`: The core workers, always serving the queue.
+/// - `extra`: Workers spawned on demand, up to `ThreadConfig::max_threads`.
+/// - `retired`: Workers that were told to exit by `set_num_threads()`, or
+///   exited after their `keep_alive`, kept until their thread is joined.
+/// - `next_id`: The `id` of the next new `Worker`, so no id is ever reused.
+struct Workers {
+    active: Vec<Worker>,
+    extra: Vec<Worker>,
+    retired: Vec<Worker>,
+    next_id: usize,
+}
+
+impl Workers {
+    /// Spawns a new `Worker`, see `Worker::new()`.
+    fn spawn(&mut self, shared: &Arc<Shared>, keep_alive: Option<Duration>) -> io::Result<Worker> {
+        let worker = Worker::new(self.next_id, Arc::clone(shared), keep_alive)?;
+
+        self.next_id += 1;
+
+        Ok(worker)
+    }
+
+    /// Moves the extra Worker `id`, whose thread is exiting, to `retired`.
+    fn release(&mut self, id: usize) {
+        if let Some(i) = self.extra.iter().position(|worker| worker.id == id) {
+            let worker = self.extra.swap_remove(i);
+
+            self.retired.push(worker);
+        }
+    }
+
+    /// Joins the retired workers whose thread has already exited.
+    fn reap(&mut self) {
+        self.retired.retain(|worker| {
+            let mut handle = worker
+                .control
+                .handle
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            match handle.take() {
+                Some(thread) if thread.is_finished() => {
+                    let _ = thread.join();
+                    false
+                }
+                Some(thread) => {
+                    *handle = Some(thread);
+                    true
+                }
+                None => false,
+            }
+        });
     }
 }
 
@@ -66,11 +525,10 @@ impl Worker {
 ///
 /// # Members
 ///
-/// - `workers`: Contains the `Worker` objects.
-/// - `sender`: Send `Message` objects to `Worker` threads.
+/// - `shared`: State shared with the `Worker` threads, including the queue
+///   and the `Worker`s themselves.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
 }
 
 impl ThreadPool {
@@ -82,72 +540,1873 @@ impl ThreadPool {
     ///
     /// - `size` euqals to `0`
     /// - `size` is too big that we can't create threads anymore
+    ///
+    /// Use `ThreadPool::try_new()` to get an error instead.
     pub fn new(size: usize) -> ThreadPool {
-        // assert if size is OK
-        assert!(size > 0);
+        match ThreadPool::try_new(size) {
+            Ok(pool) => pool,
+            Err(e) => panic!("failed to create thread pool: {}", e),
+        }
+    }
 
-        // init workers
-        let mut workers = Vec::with_capacity(size);
+    /// Creates a thread pool, returning an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// - `PoolBuildError::ZeroSize`: `size` euqals to `0`.
+    /// - `PoolBuildError::Spawn`: The OS refused to create a thread. The
+    ///   workers spawned so far are terminated and joined before returning.
+    pub fn try_new(size: usize) -> Result<ThreadPool, PoolBuildError> {
+        ThreadPoolBuilder::new().num_threads(size).build()
+    }
 
-        let (sender, receiver) = mpsc::channel();
+    /// Creates a thread pool of `size` threads set up by `config`.
+    ///
+    /// Called by `ThreadPoolBuilder::build()`.
+    fn with_config(
+        size: usize,
+        queue: QueueConfig,
+        config: ThreadConfig,
+    ) -> Result<ThreadPool, PoolBuildError> {
+        if size == 0 {
+            return Err(PoolBuildError::ZeroSize);
+        }
 
-        // Copy the receiver,As Atomic RC and Mutex.
-        let receiver = Arc::new(Mutex::new(receiver));
+        if queue.capacity == Some(0) {
+            return Err(PoolBuildError::ZeroCapacity);
+        }
 
-        for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&receiver)))
+        if matches!(config.max_threads, Some(max_threads) if max_threads < size) {
+            return Err(PoolBuildError::MaxBelowSize);
         }
 
-        return ThreadPool { workers, sender };
+        let shared = Arc::new(Shared {
+            queue: JobQueue::new(queue),
+            config,
+            workers: Mutex::new(Workers {
+                active: Vec::with_capacity(size),
+                extra: Vec::new(),
+                retired: Vec::new(),
+                next_id: 0,
+            }),
+            counters: Counters::default(),
+            live_workers: AtomicUsize::new(0),
+            exit_lock: Mutex::new(()),
+            exited: Condvar::new(),
+            timer: Timer::new(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        });
+
+        let pool = ThreadPool { shared };
+
+        // On error `pool` is dropped here, which cleans up the workers so far.
+        pool.set_num_threads(size)?;
+
+        Ok(pool)
     }
 
     /// Run the given function or closure in the pool.
     ///
-    /// # Steps
+    /// # Panics
     ///
-    /// 1. Construct A `Box<F>` object.
-    /// 2. Send it to the `Worker`s.
+    /// This function will panic when `try_run` returns an error.
     pub fn run<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-
-        self.sender.send(Message::NewJob(job)).unwrap();
+        if let Err(e) = self.try_run(f) {
+            panic!("failed to run job: {}", e);
+        }
     }
-}
 
-impl Drop for ThreadPool {
-    /// Drop the ThreadPool.
+    /// Run the given function or closure in the pool, handing it back on failure.
     ///
     /// # Steps
     ///
-    /// 1. Send `Message::Terminate` to all `Worker`s in `workers`
-    /// 2. For each `Worker`, use `handle.join().unwrap()` to terminate the thread.
-    fn drop(&mut self) {
-        for _ in &mut self.workers.iter() {
-            self.sender.send(Message::Terminate).unwrap();
+    /// 1. Check that the pool can still take the job.
+    /// 2. Push it to the queue, following the `OverflowPolicy` if the queue
+    ///    is bounded and full.
+    ///
+    /// # Errors
+    ///
+    /// - `SubmitError::Closed`: Every worker thread has exited, so nobody
+    ///   would ever run the job.
+    /// - `SubmitError::Full`: The queue is full and the policy is
+    ///   `OverflowPolicy::Reject`.
+    /// - `SubmitError::Shutdown`: The pool has been shut down.
+    pub fn try_run<F>(&self, f: F) -> Result<(), SubmitError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_run_with_priority(Priority::Normal, f)
+    }
+
+    /// Run the given function or closure in the pool, ahead of or behind
+    /// the jobs of other priorities.
+    ///
+    /// # Notes
+    ///
+    /// Jobs of the same priority still run in order. A job left waiting
+    /// gains priority over time, see `ThreadPoolBuilder::priority_aging`, so
+    /// even `Priority::Low` jobs run eventually.
+    ///
+    /// In work-stealing mode, a worker still takes the jobs of its own deque
+    /// first, whatever their priority.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic when `try_run_with_priority` returns an error.
+    pub fn run_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(e) = self.try_run_with_priority(priority, f) {
+            panic!("failed to run job: {}", e);
         }
+    }
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker #{}", worker.id);
+    /// Like `run_with_priority`, handing the job back on failure.
+    ///
+    /// # Errors
+    ///
+    /// See `try_run`.
+    pub fn try_run_with_priority<F>(&self, priority: Priority, f: F) -> Result<(), SubmitError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(priority, f)
+    }
 
-            if let Some(handle) = worker.handle.take() {
-                handle.join().unwrap();
-            }
-        }
+    /// Run the given function or closure in the pool, and get its return value back.
+    ///
+    /// # Notes
+    ///
+    /// If `f` panics, the payload goes to the handle as `JoinError::Panicked`,
+    /// and the job is still counted in `PoolStats::panicked_jobs`.
+    ///
+    /// If the pool can't take the job (see `try_run`), the job is dropped and
+    /// the handle reports `JoinError::Disconnected` instead of panicking.
+    ///
+    /// `TaskHandle::cancel()` takes the job back if it hasn't started yet.
+    pub fn submit<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit_cancellable(move |_| f())
     }
-}
 
-#[cfg(test)]
+    /// Like `submit`, but `f` gets a `CancellationToken` to check while it runs.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let pool = ThreadPool::new(1);
+    ///
+    /// let handle = pool.submit_cancellable(|token| {
+    ///     let mut rounds = 0;
+    ///
+    ///     while !token.is_cancelled() {
+    ///         rounds += 1;
+    ///         thread::sleep(Duration::from_millis(1));
+    ///     }
+    ///
+    ///     rounds
+    /// });
+    ///
+    /// thread::sleep(Duration::from_millis(10));
+    /// handle.cancel();
+    ///
+    /// assert!(handle.join().unwrap() > 0);
+    /// ```
+    ///
+    /// # Steps
+    ///
+    /// 1. Create a `CancellationToken` and a `Completer` / `TaskHandle` pair.
+    /// 2. Wrap `f` so it is skipped if cancelled before it starts, and its
+    ///    return value goes to the `Completer`, and `run` it.
+    /// 3. Return the `TaskHandle` to the caller.
+    ///
+    /// # Notes
+    ///
+    /// Every shutdown, graceful or not, cancels the tokens of all jobs. Queued
+    /// jobs still start on a graceful shutdown, but can see that right away.
+    pub fn submit_cancellable<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let token = CancellationToken::new(Arc::clone(&self.shared.shutdown));
 
-mod test {
+        self.submit_with_token(token, f)
+    }
 
-    use crate::*;
+    /// Like `submit_cancellable`, with a `token` made by the caller.
+    pub(crate) fn submit_with_token<F, T>(&self, token: CancellationToken, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (completer, handle) = handle::channel(token.clone());
 
-    #[test]
-    #[should_panic]
-    fn thread_pool_test_new_1() {
-        ThreadPool::new(1000000);
+        let _ = self.try_run(move || {
+            if !token.start() {
+                completer.complete(Err(JoinError::Cancelled));
+                return;
+            }
+
+            match panic::catch_unwind(AssertUnwindSafe(|| f(&token))) {
+                Ok(value) => completer.complete(Ok(value)),
+                Err(payload) => {
+                    completer.complete(Err(JoinError::Panicked(payload)));
+                    rethrow_reported("job panicked, payload sent to its TaskHandle");
+                }
+            }
+        });
+
+        handle
+    }
+
+    /// Shuts the pool down gracefully.
+    ///
+    /// # Steps
+    ///
+    /// 1. Cancel every `CancellationToken`, and stop the timer, dropping the
+    ///    jobs scheduled for later.
+    /// 2. Reject new jobs with `SubmitError::Shutdown`.
+    /// 3. Let the `Worker`s finish every job already queued.
+    /// 4. Join the `Worker` threads.
+    ///
+    /// # Notes
+    ///
+    /// Calling it again, or dropping the pool afterwards, does nothing.
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.timer.shutdown();
+        self.shared.queue.close(false);
+        self.join_workers();
+    }
+
+    /// Shuts the pool down without running the queued jobs.
+    ///
+    /// # Steps
+    ///
+    /// 1. Cancel every `CancellationToken`, and stop the timer, dropping the
+    ///    jobs scheduled for later.
+    /// 2. Reject new jobs with `SubmitError::Shutdown`.
+    /// 3. Let the `Worker`s finish the jobs they are running, and set the
+    ///    queued ones aside.
+    /// 4. Join the `Worker` threads, and return the jobs that never started.
+    ///
+    /// # Notes
+    ///
+    /// The returned jobs can be run or dropped. Dropping one made by `submit`
+    /// makes its `TaskHandle` report `JoinError::Disconnected`.
+    pub fn shutdown_now(&self) -> Vec<Job> {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.timer.shutdown();
+
+        let unstarted = self.shared.queue.close(true);
+
+        self.join_workers();
+
+        unstarted
+    }
+
+    /// Shuts the pool down gracefully, waiting at most `timeout` for it.
+    ///
+    /// Returns `true` if every worker finished in time. Otherwise the workers
+    /// keep draining the queue in the background, and are joined when the
+    /// pool is dropped.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> bool {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.timer.shutdown();
+        self.shared.queue.close(false);
+
+        if !self.shared.wait_exited(Instant::now() + timeout) {
+            return false;
+        }
+
+        self.join_workers();
+
+        true
+    }
+
+    /// For each `Worker`, use `handle.join()` to wait for the thread.
+    ///
+    /// # Notes
+    ///
+    /// A thread that died was already replaced through its `Sentinel`, so its
+    /// failed `join()` is ignored and the replacement is joined in turn.
+    fn join_workers(&self) {
+        // Not joined under the lock, a job may still be calling `set_num_threads`.
+        let controls: Vec<_> = {
+            let workers = self.shared.workers();
+
+            workers
+                .active
+                .iter()
+                .chain(&workers.extra)
+                .chain(&workers.retired)
+                .map(|worker| (worker.id, Arc::clone(&worker.control)))
+                .collect()
+        };
+
+        for (id, control) in controls {
+            loop {
+                let handle = control
+                    .handle
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take();
+
+                match handle {
+                    // The last `ThreadPool` handle may be dropped by a job, on
+                    // this very worker, which exits once the job returns.
+                    Some(handle) if handle.thread().id() == thread::current().id() => break,
+                    Some(handle) => {
+                        self.shared.config.sink.emit(id, EventKind::ShuttingDown);
+
+                        let _ = handle.join();
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Blocks until no job is queued or running.
+    ///
+    /// # Notes
+    ///
+    /// Unlike `shutdown()`, the pool keeps taking jobs, so new ones may keep
+    /// it busy. Jobs scheduled for later and pending futures only count once
+    /// they are queued.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from a job of this pool, which would wait on itself.
+    pub fn wait_idle(&self) {
+        self.assert_not_worker("wait_idle");
+        self.shared.queue.wait_idle(None);
+    }
+
+    /// Blocks for at most `timeout` until no job is queued or running.
+    ///
+    /// Returns `true` if the pool went idle in time.
+    ///
+    /// # Panics
+    ///
+    /// See `wait_idle()`.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        self.assert_not_worker("wait_idle_timeout");
+        self.shared.queue.wait_idle(Some(Instant::now() + timeout))
+    }
+
+    fn assert_not_worker(&self, method: &str) {
+        assert!(
+            self.shared.queue.current_worker().is_none(),
+            "{}() called from a job of the same pool",
+            method
+        );
+    }
+
+    /// The number of worker threads the pool is set to.
+    ///
+    /// Workers retiring after `set_num_threads()` don't count, even if they
+    /// are still finishing their job, and neither do the extra workers
+    /// spawned on demand.
+    pub fn num_threads(&self) -> usize {
+        self.shared.workers().active.len()
+    }
+
+    /// Grows or shrinks the pool to `num_threads` worker threads.
+    ///
+    /// # Steps
+    ///
+    /// 1. Join the workers retired earlier that have exited by now.
+    /// 2. Spawn new `Worker`s with fresh ids, or tell the newest ones to
+    ///    retire. A retiring worker finishes its current job, hands the jobs
+    ///    of its local deque to the others, and exits. This doesn't wait for
+    ///    it.
+    ///
+    /// # Notes
+    ///
+    /// Queued jobs are kept either way. Does nothing once the pool is shut
+    /// down.
+    ///
+    /// This sets the core size of an elastic pool, see
+    /// `ThreadPoolBuilder::max_threads()`. Extra workers are only spawned
+    /// while the total stays below `max_threads`, so never once the core
+    /// size reaches it.
+    ///
+    /// # Errors
+    ///
+    /// - `PoolBuildError::ZeroSize`: `num_threads` equals to `0`.
+    /// - `PoolBuildError::MaxBelowSize`: The pool is elastic and
+    ///   `num_threads` is above its `max_threads`, like in `build()`.
+    /// - `PoolBuildError::Spawn`: The OS refused to create a thread. The
+    ///   workers spawned so far are kept.
+    pub fn set_num_threads(&self, num_threads: usize) -> Result<(), PoolBuildError> {
+        if num_threads == 0 {
+            return Err(PoolBuildError::ZeroSize);
+        }
+
+        if matches!(self.shared.config.max_threads, Some(max_threads) if num_threads > max_threads)
+        {
+            return Err(PoolBuildError::MaxBelowSize);
+        }
+
+        let mut workers = self.shared.workers();
+
+        if self.shared.queue.is_closed() {
+            return Ok(());
+        }
+
+        workers.reap();
+
+        while workers.active.len() < num_threads {
+            let worker = workers
+                .spawn(&self.shared, None)
+                .map_err(PoolBuildError::Spawn)?;
+
+            workers.active.push(worker);
+        }
+
+        if workers.active.len() > num_threads {
+            let surplus = workers.active.split_off(num_threads);
+
+            for worker in &surplus {
+                worker.control.retire.store(true, Ordering::SeqCst);
+            }
+
+            workers.retired.extend(surplus);
+            self.shared.queue.wake_all();
+        }
+
+        Ok(())
+    }
+
+    /// Returns a snapshot of the pool's statistics.
+    ///
+    /// # Notes
+    ///
+    /// Cheap enough to poll for a dashboard: only the queue lock is taken,
+    /// briefly, to count the queued jobs.
+    pub fn stats(&self) -> PoolStats {
+        let queued_jobs = self.shared.queue.len();
+        let live_workers = self.shared.live_workers.load(Ordering::SeqCst);
+
+        self.shared.counters.snapshot(queued_jobs, live_workers)
+    }
+
+    /// Blocks until `latch` is set.
+    ///
+    /// # Notes
+    ///
+    /// On a worker of this pool, runs queued jobs meanwhile instead of
+    /// sleeping, so a job waiting on another job can't take the last free
+    /// thread away from it.
+    fn wait_helping(&self, latch: &Latch) {
+        let (id, local) = match self.shared.queue.current_worker() {
+            Some(worker) => worker,
+            None => return latch.wait(),
+        };
+
+        while !latch.is_set() {
+            match self.shared.queue.try_pop(local.as_deref()) {
+                Some(queued) => self.shared.execute(id, queued, true),
+                // The latch isn't tied to the queue, so poll both.
+                None => latch.wait_timeout(Duration::from_millis(1)),
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Drop the ThreadPool.
+    ///
+    /// # Notes
+    ///
+    /// Same as `shutdown()`: every queued job still runs first. Use
+    /// `shutdown_now()` or `shutdown_timeout()` before dropping to avoid
+    /// waiting on a long backlog.
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::*;
+    use std::sync::mpsc;
+
+    #[test]
+    #[should_panic]
+    fn thread_pool_test_new_1() {
+        ThreadPool::new(1000000);
+    }
+
+    #[test]
+    fn thread_pool_test_try_new_1() {
+        assert!(matches!(
+            ThreadPool::try_new(0),
+            Err(PoolBuildError::ZeroSize)
+        ));
+        assert!(ThreadPool::try_new(2).is_ok());
+    }
+
+    #[test]
+    fn thread_pool_test_builder_1() {
+        use std::sync::atomic::AtomicUsize;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let (started_hook, stopped_hook) = (Arc::clone(&started), Arc::clone(&stopped));
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(3)
+            .thread_name(|id| format!("test-worker-{}", id))
+            .stack_size(256 * 1024)
+            .on_thread_start(move |_| {
+                started_hook.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_thread_stop(move |_| {
+                stopped_hook.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+
+        let name = pool.submit(|| thread::current().name().map(String::from));
+
+        assert!(name.join().unwrap().unwrap().starts_with("test-worker-"));
+
+        drop(pool);
+
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn thread_pool_test_events_1() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .on_event(move |event| sink.lock().unwrap().push(*event))
+            .build()
+            .unwrap();
+
+        pool.run(|| panic!("logged"));
+        drop(pool);
+
+        let events = events.lock().unwrap();
+
+        // `ShuttingDown` comes from the dropping thread, so it may come first.
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|e| e.worker == 0));
+
+        for kind in &[
+            EventKind::JobStarted,
+            EventKind::JobPanicked,
+            EventKind::Terminating,
+            EventKind::ShuttingDown,
+        ] {
+            assert!(events.iter().any(|e| e.kind == *kind));
+        }
+    }
+
+    #[test]
+    fn thread_pool_test_try_run_1() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+
+        assert!(pool.try_run(move || tx.send(1).unwrap()).is_ok());
+        assert_eq!(rx.recv().unwrap(), 1);
+    }
+
+    #[test]
+    fn thread_pool_test_overflow_1() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::Reject)
+            .build()
+            .unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        // Keep the only worker busy, then fill the queue.
+        pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+        pool.run(|| {});
+
+        assert!(matches!(pool.try_run(|| {}), Err(SubmitError::Full(_))));
+
+        tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn thread_pool_test_overflow_2() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::CallerRuns)
+            .build()
+            .unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+        pool.run(|| {});
+
+        let caller = thread::current().id();
+        let ran_on = Arc::new(Mutex::new(None));
+        let ran_on_job = Arc::clone(&ran_on);
+
+        pool.run(move || *ran_on_job.lock().unwrap() = Some(thread::current().id()));
+
+        assert_eq!(*ran_on.lock().unwrap(), Some(caller));
+
+        tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn thread_pool_test_overflow_3() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::DropOldest)
+            .build()
+            .unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        let oldest = pool.submit(|| 1);
+        let newest = pool.submit(|| 2);
+
+        tx.send(()).unwrap();
+
+        assert!(matches!(oldest.join(), Err(JoinError::Disconnected)));
+        assert_eq!(newest.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn thread_pool_test_overflow_4() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(2)
+            .overflow_policy(OverflowPolicy::DropOldest)
+            .build()
+            .unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        let (urgent_tx, urgent_rx) = mpsc::channel();
+
+        for i in 0..2 {
+            let urgent_tx = urgent_tx.clone();
+
+            pool.run_with_priority(Priority::High, move || urgent_tx.send(i).unwrap());
+        }
+
+        // A full queue of `High` jobs drops the `Low` newcomer, not one of them.
+        let (low_tx, low_rx) = mpsc::channel::<()>();
+
+        pool.run_with_priority(Priority::Low, move || low_tx.send(()).unwrap());
+
+        drop(urgent_tx);
+        tx.send(()).unwrap();
+
+        assert_eq!(urgent_rx.iter().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(low_rx.recv().is_err());
+    }
+
+    #[test]
+    fn thread_pool_test_shutdown_1() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..16).map(|i| pool.submit(move || i)).collect();
+
+        pool.shutdown();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.try_join().unwrap(), i);
+        }
+
+        assert!(matches!(pool.try_run(|| {}), Err(SubmitError::Shutdown(_))));
+    }
+
+    #[test]
+    fn thread_pool_test_shutdown_2() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let running = pool.submit(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        let queued: Vec<_> = (0..3).map(|i| pool.submit(move || i)).collect();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            tx.send(()).unwrap();
+        });
+
+        let unstarted = pool.shutdown_now();
+
+        assert!(running.try_join().is_ok());
+        assert_eq!(unstarted.len(), 3);
+
+        drop(unstarted);
+
+        for handle in queued {
+            assert!(matches!(handle.try_join(), Err(JoinError::Disconnected)));
+        }
+    }
+
+    #[test]
+    fn thread_pool_test_shutdown_3() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+
+        pool.run(move || rx.recv().unwrap());
+
+        assert!(!pool.shutdown_timeout(Duration::from_millis(10)));
+
+        tx.send(()).unwrap();
+
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_test_shutdown_4() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let pool = {
+            let stopped = Arc::clone(&stopped);
+
+            ThreadPoolBuilder::new()
+                .num_threads(2)
+                .on_thread_stop(move |_| {
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                })
+                .build()
+                .unwrap()
+        };
+        let pool = Arc::new(pool);
+        let (tx, rx) = mpsc::channel();
+
+        pool.run({
+            let pool = Arc::clone(&pool);
+            let stopped = Arc::clone(&stopped);
+
+            move || {
+                thread::sleep(Duration::from_millis(20));
+
+                // The last `Arc`, dropped on a worker: the other one is joined.
+                drop(pool);
+                tx.send(stopped.load(Ordering::SeqCst)).unwrap();
+            }
+        });
+
+        drop(pool);
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn thread_pool_test_work_stealing_1() {
+        let pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(4)
+                .work_stealing(true)
+                .build()
+                .unwrap(),
+        );
+        let (tx, rx) = mpsc::channel();
+
+        // Every job spawns its children from inside the pool.
+        fn tree(pool: Arc<ThreadPool>, depth: u32, tx: mpsc::Sender<u32>) {
+            tx.send(depth).unwrap();
+
+            if depth > 0 {
+                for _ in 0..2 {
+                    let (child_pool, tx) = (Arc::clone(&pool), tx.clone());
+
+                    pool.run(move || tree(child_pool, depth - 1, tx));
+                }
+            }
+        }
+
+        let root_pool = Arc::clone(&pool);
+
+        pool.run(move || tree(root_pool, 8, tx));
+
+        // All senders are gone once the whole tree has run.
+        assert_eq!(rx.iter().count(), (1 << 9) - 1);
+
+        // Make sure the last `Arc` isn't dropped on a worker.
+        pool.shutdown();
+    }
+
+    #[test]
+    fn thread_pool_test_work_stealing_2() {
+        let pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(1)
+                .work_stealing(true)
+                .build()
+                .unwrap(),
+        );
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (inner_pool, inner_order) = (Arc::clone(&pool), Arc::clone(&order));
+
+        let outer = pool.submit(move || {
+            for i in 0..3 {
+                let order = Arc::clone(&inner_order);
+
+                inner_pool.run(move || order.lock().unwrap().push(i));
+            }
+        });
+
+        outer.join().unwrap();
+        pool.shutdown();
+
+        // The owner pops its local deque newest first.
+        assert_eq!(*order.lock().unwrap(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn thread_pool_test_work_stealing_3() {
+        let pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(1)
+                .work_stealing(true)
+                .build()
+                .unwrap(),
+        );
+        let ran = Arc::new(AtomicBool::new(false));
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (inner_pool, inner_ran) = (Arc::clone(&pool), Arc::clone(&ran));
+
+        let outer = pool.submit(move || {
+            gate_rx.recv().unwrap();
+
+            // Would go to the local deque, after `shutdown_now` drained it.
+            let result = inner_pool.try_run(move || inner_ran.store(true, Ordering::SeqCst));
+
+            matches!(result, Err(SubmitError::Shutdown(_)))
+        });
+
+        let stopper = {
+            let pool = Arc::clone(&pool);
+
+            thread::spawn(move || pool.shutdown_now())
+        };
+
+        while !matches!(pool.try_run(|| ()), Err(SubmitError::Shutdown(_))) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        gate_tx.send(()).unwrap();
+
+        // Only the probes above were still queued.
+        stopper.join().unwrap();
+
+        assert!(outer.join().unwrap());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn thread_pool_test_scope_1() {
+        let pool = ThreadPool::new(3);
+        let input: Vec<u64> = (1..=100).collect();
+        let mut sums = vec![0; 4];
+
+        pool.scope(|s| {
+            for (chunk, sum) in input.chunks(25).zip(sums.iter_mut()) {
+                s.spawn(move || *sum = chunk.iter().sum());
+            }
+        });
+
+        assert_eq!(sums, vec![325, 950, 1575, 2200]);
+    }
+
+    #[test]
+    fn thread_pool_test_scope_2() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("scoped"));
+
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "scoped");
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn thread_pool_test_scope_3() {
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner_pool = Arc::clone(&pool);
+
+        // The only worker opens a scope, then nested ones inside its jobs.
+        let handle = pool.submit(move || {
+            let mut sums = [0; 2];
+
+            inner_pool.scope(|s| {
+                for (i, sum) in sums.iter_mut().enumerate() {
+                    let pool = &inner_pool;
+
+                    s.spawn(move || {
+                        pool.scope(|s| s.spawn(|| *sum = i + 1));
+                    });
+                }
+            });
+
+            sums
+        });
+
+        assert_eq!(handle.join_timeout(Duration::from_secs(2)).unwrap(), [1, 2]);
+
+        pool.shutdown();
+    }
+
+    #[test]
+    fn thread_pool_test_submit_1() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..8).map(|i| pool.submit(move || i * i)).collect();
+        let results: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(results, vec![0, 1, 4, 9, 16, 25, 36, 49]);
+    }
+
+    #[test]
+    fn thread_pool_test_submit_2() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = pool.submit(move || rx.recv().unwrap());
+
+        assert!(matches!(handle.try_join(), Err(JoinError::Pending)));
+        assert!(matches!(
+            handle.join_timeout(std::time::Duration::from_millis(10)),
+            Err(JoinError::Timeout)
+        ));
+
+        tx.send(()).unwrap();
+
+        assert!(handle
+            .join_timeout(std::time::Duration::from_secs(5))
+            .is_ok());
+        assert!(matches!(handle.try_join(), Err(JoinError::Disconnected)));
+    }
+
+    #[test]
+    fn thread_pool_test_panic_1() {
+        let pool = ThreadPool::new(1);
+
+        pool.run(|| panic!("run panicked"));
+        let err = pool
+            .submit(|| -> i32 { panic!("submit panicked") })
+            .join()
+            .unwrap_err();
+
+        assert_eq!(err.panic_message(), Some("submit panicked"));
+
+        // The only worker survived both panics.
+        assert_eq!(pool.submit(|| 42).join().unwrap(), 42);
+        assert_eq!(pool.stats().panicked_jobs, 2);
+    }
+
+    #[test]
+    fn thread_pool_test_respawn_1() {
+        // A panic payload that panics again when the worker drops it, which
+        // kills the worker thread outside of `catch_unwind`.
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("bomb");
+            }
+        }
+
+        let pool = ThreadPool::new(1);
+
+        pool.run(|| std::panic::panic_any(Bomb));
+
+        assert_eq!(pool.submit(|| 42).join().unwrap(), 42);
+
+        // Counted once the replacement is up, which may run the job first.
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while pool.stats().respawned_workers == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(pool.stats().respawned_workers, 1);
+    }
+
+    #[test]
+    fn thread_pool_test_respawn_2() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .on_thread_start(|id| {
+                if id == 0 {
+                    panic!("worker 0 fails to start");
+                }
+            })
+            .on_event(move |event| sink.lock().unwrap().push(*event))
+            .build()
+            .unwrap();
+
+        assert_eq!(pool.submit(|| 42).join().unwrap(), 42);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while pool.stats().idle_workers > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Worker 0 is gone for good instead of dying over and over.
+        thread::sleep(Duration::from_millis(20));
+
+        let stats = pool.stats();
+
+        assert_eq!(stats.idle_workers, 1);
+        assert_eq!(stats.respawned_workers, 0);
+
+        // Reported as lost, not as respawning.
+        let events = events.lock().unwrap();
+        let start_failed = Event {
+            worker: 0,
+            kind: EventKind::StartFailed,
+        };
+
+        assert!(events.contains(&start_failed));
+        assert!(!events.iter().any(|event| event.kind == EventKind::Died));
+    }
+
+    #[test]
+    fn thread_pool_test_join_1() {
+        fn fib(pool: &ThreadPool, n: u64) -> u64 {
+            if n < 2 {
+                return n;
+            }
+
+            let (a, b) = pool.join(|| fib(pool, n - 1), || fib(pool, n - 2));
+
+            a + b
+        }
+
+        let pool = Arc::new(ThreadPool::new(2));
+        let worker_pool = Arc::clone(&pool);
+
+        // Nested joins on the workers themselves must not deadlock.
+        let handle = pool.submit(move || fib(&worker_pool, 16));
+
+        assert_eq!(handle.join().unwrap(), 987);
+        assert_eq!(fib(&pool, 12), 144);
+
+        pool.shutdown();
+    }
+
+    #[test]
+    fn thread_pool_test_join_2() {
+        let pool = ThreadPool::new(2);
+        let mut left = 0;
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.join(|| left = 1, || panic!("right"))
+        }));
+
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "right");
+        assert_eq!(left, 1);
+        assert_eq!(pool.join(|| 1, || 2), (1, 2));
+    }
+
+    #[test]
+    fn thread_pool_test_par_1() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u64> = (0..10_000).collect();
+        let visited = AtomicUsize::new(0);
+
+        assert_eq!(
+            pool.par_map(&numbers, |n| n * 2),
+            (0..20_000).step_by(2).collect::<Vec<_>>()
+        );
+        assert_eq!(pool.par_filter(&numbers, |n| n % 1000 == 0).len(), 10);
+        assert_eq!(pool.par_reduce(&numbers, || 0, |a, b| a + b), 49_995_000);
+        assert_eq!(pool.par_reduce(&[], || 7, |a: u64, b| a + b), 7);
+
+        pool.par_for_each(&numbers, |_| {
+            visited.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(visited.load(Ordering::SeqCst), 10_000);
+    }
+
+    #[test]
+    fn thread_pool_test_par_2() {
+        let pool = ThreadPool::new(2);
+        let numbers: Vec<u32> = (0..1000).collect();
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.par_map(&numbers, |&n| if n == 777 { panic!("par") } else { n })
+        }));
+
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "par");
+        assert_eq!(pool.par_map(&numbers, |&n| n).len(), 1000);
+    }
+
+    #[test]
+    fn thread_pool_test_priority_1() {
+        let pool = ThreadPool::new(1);
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+
+        pool.run(move || gate_rx.recv().unwrap());
+
+        for (priority, name) in [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal"),
+            (Priority::High, "high"),
+        ] {
+            let tx = tx.clone();
+
+            pool.run_with_priority(priority, move || tx.send(name).unwrap());
+        }
+
+        gate_tx.send(()).unwrap();
+
+        let order: Vec<_> = rx.iter().take(3).collect();
+
+        assert_eq!(order, vec!["high", "normal", "low"]);
+    }
+
+    #[test]
+    fn thread_pool_test_priority_2() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .priority_aging(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+
+        pool.run(move || gate_rx.recv().unwrap());
+
+        let low_tx = tx.clone();
+
+        pool.run_with_priority(Priority::Low, move || low_tx.send("low").unwrap());
+
+        // Long enough for the low job to age past `High`.
+        thread::sleep(Duration::from_millis(50));
+
+        pool.run_with_priority(Priority::High, move || tx.send("high").unwrap());
+        gate_tx.send(()).unwrap();
+
+        let order: Vec<_> = rx.iter().take(2).collect();
+
+        assert_eq!(order, vec!["low", "high"]);
+    }
+
+    #[test]
+    fn thread_pool_test_schedule_1() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+
+        let late_tx = tx.clone();
+
+        pool.schedule_after(Duration::from_millis(40), move || {
+            late_tx.send("late").unwrap()
+        });
+        pool.schedule_at(start + Duration::from_millis(20), move || {
+            tx.send("early").unwrap()
+        });
+
+        let cancelled = pool.schedule_after(Duration::from_millis(10), || panic!("cancelled"));
+
+        cancelled.cancel();
+
+        assert!(cancelled.is_cancelled());
+        assert_eq!(rx.recv().unwrap(), "early");
+        assert_eq!(rx.recv().unwrap(), "late");
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(pool.stats().panicked_jobs, 0);
+    }
+
+    #[test]
+    fn thread_pool_test_schedule_2() {
+        let pool = ThreadPool::new(2);
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ticks);
+
+        let handle =
+            pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(5), move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+
+        while ticks.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        handle.cancel();
+        pool.shutdown();
+
+        let after_cancel = ticks.load(Ordering::SeqCst);
+
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(ticks.load(Ordering::SeqCst), after_cancel);
+
+        // Pending jobs are dropped on shutdown, new ones come back cancelled.
+        assert!(pool.schedule_after(Duration::ZERO, || ()).is_cancelled());
+    }
+
+    #[test]
+    fn thread_pool_test_cancel_1() {
+        let pool = ThreadPool::new(1);
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let gate = pool.submit(move || gate_rx.recv().unwrap());
+        let counter = Arc::clone(&ran);
+        let queued = pool.submit(move || counter.fetch_add(1, Ordering::SeqCst));
+
+        assert!(queued.cancel());
+        assert!(matches!(queued.join(), Err(JoinError::Cancelled)));
+
+        gate_tx.send(()).unwrap();
+
+        while !gate.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(!gate.cancel());
+        assert!(gate.join().is_ok());
+
+        pool.shutdown();
+
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn thread_pool_test_cancel_2() {
+        let pool = ThreadPool::new(1);
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let handle = pool.submit_cancellable(move |token| {
+            started_tx.send(()).unwrap();
+
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            "stopped"
+        });
+
+        started_rx.recv().unwrap();
+
+        // Would wait forever if shutdown didn't cancel the token.
+        pool.shutdown();
+
+        assert_eq!(handle.join().unwrap(), "stopped");
+    }
+
+    #[test]
+    fn thread_pool_test_resize_1() {
+        let (start_tx, start_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let start_tx = Mutex::new(start_tx);
+        let stop_tx = Mutex::new(stop_tx);
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .on_thread_start(move |id| start_tx.lock().unwrap().send(id).unwrap())
+            .on_thread_stop(move |id| stop_tx.lock().unwrap().send(id).unwrap())
+            .build()
+            .unwrap();
+
+        pool.set_num_threads(4).unwrap();
+
+        let mut started: Vec<usize> = start_rx.iter().take(4).collect();
+
+        started.sort_unstable();
+        assert_eq!(started, vec![0, 1, 2, 3]);
+
+        pool.set_num_threads(1).unwrap();
+
+        let mut stopped: Vec<usize> = stop_rx.iter().take(3).collect();
+
+        stopped.sort_unstable();
+        assert_eq!(stopped, vec![1, 2, 3]);
+        assert_eq!(pool.num_threads(), 1);
+
+        // Ids are never reused.
+        pool.set_num_threads(2).unwrap();
+
+        assert_eq!(start_rx.recv().unwrap(), 4);
+        assert!(matches!(
+            pool.set_num_threads(0),
+            Err(PoolBuildError::ZeroSize)
+        ));
+    }
+
+    #[test]
+    fn thread_pool_test_resize_2() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .work_stealing(true)
+            .build()
+            .unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..100 {
+            let count = Arc::clone(&count);
+
+            pool.run(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        pool.set_num_threads(1).unwrap();
+        pool.shutdown();
+
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn thread_pool_test_elastic_1() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gate_rx = Arc::new(Mutex::new(gate_rx));
+        let (tx, rx) = mpsc::channel();
+
+        // Every job blocks, so each new one finds no idle worker.
+        for _ in 0..5 {
+            let gate_rx = Arc::clone(&gate_rx);
+            let tx = tx.clone();
+
+            pool.run(move || {
+                tx.send(thread::current().id()).unwrap();
+                gate_rx.lock().unwrap().recv().unwrap();
+            });
+        }
+
+        let threads: std::collections::HashSet<_> = rx.iter().take(3).collect();
+
+        assert_eq!(threads.len(), 3);
+        assert_eq!(pool.shared.live_workers.load(Ordering::SeqCst), 3);
+
+        for _ in 0..5 {
+            gate_tx.send(()).unwrap();
+        }
+
+        rx.iter().take(2).for_each(drop);
+
+        // The extra workers exit after `keep_alive`, the core one stays.
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while pool.shared.live_workers.load(Ordering::SeqCst) > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(pool.shared.live_workers.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.num_threads(), 1);
+        assert_eq!(pool.submit(|| 7).join().unwrap(), 7);
+    }
+
+    #[test]
+    fn thread_pool_test_elastic_2() {
+        let result = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .max_threads(2)
+            .build();
+
+        assert!(matches!(result, Err(PoolBuildError::MaxBelowSize)));
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(2)
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            pool.set_num_threads(3),
+            Err(PoolBuildError::MaxBelowSize)
+        ));
+        assert!(pool.set_num_threads(2).is_ok());
+        assert_eq!(pool.num_threads(), 2);
+    }
+
+    #[test]
+    fn thread_pool_test_elastic_3() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(4)
+            .keep_alive(Duration::from_micros(200))
+            .build()
+            .unwrap();
+
+        // Bursts grow the pool, and extra workers keep timing out in between,
+        // racing with the wake-ups of the next burst. None may get lost.
+        for i in 0..20_000 {
+            for _ in 0..3 {
+                pool.run(|| ());
+            }
+
+            let result = pool.submit(move || i).join_timeout(Duration::from_secs(1));
+
+            assert_eq!(result.unwrap(), i, "stalled, {:?}", pool.stats());
+        }
+    }
+
+    #[test]
+    fn thread_pool_test_stats_1() {
+        let pool = ThreadPool::new(1);
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+
+        pool.run(move || gate_rx.recv().unwrap());
+
+        while pool.stats().active_workers == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        pool.run(|| thread::sleep(Duration::from_millis(10)));
+        pool.run(|| panic!("stats"));
+
+        let busy = pool.stats();
+
+        assert_eq!(
+            (busy.queued_jobs, busy.active_workers, busy.idle_workers),
+            (2, 1, 0)
+        );
+
+        thread::sleep(Duration::from_millis(10));
+        gate_tx.send(()).unwrap();
+        pool.shutdown();
+
+        let done = pool.stats();
+
+        assert_eq!(
+            (done.completed_jobs, done.panicked_jobs, done.queued_jobs),
+            (2, 1, 0)
+        );
+        assert!(done.total_run_time >= Duration::from_millis(20));
+        assert!(done.avg_wait_time >= Duration::from_millis(10) / 3);
+        assert_eq!(done.avg_run_time, done.total_run_time / 3);
+    }
+
+    #[test]
+    fn thread_pool_test_future_1() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        /// Pending a few times, waking itself from another thread each time.
+        struct Countdown(usize);
+
+        impl Future for Countdown {
+            type Output = &'static str;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                if self.0 == 0 {
+                    return Poll::Ready("liftoff");
+                }
+
+                self.0 -= 1;
+
+                let waker = cx.waker().clone();
+
+                thread::spawn(move || waker.wake());
+
+                Poll::Pending
+            }
+        }
+
+        let pool = ThreadPool::new(2);
+        let handle = pool.spawn_future(Countdown(5));
+
+        assert_eq!(handle.join().unwrap(), "liftoff");
+
+        // Each poll ran as a job.
+        pool.shutdown();
+
+        assert_eq!(pool.stats().completed_jobs, 6);
+    }
+
+    #[test]
+    fn thread_pool_test_future_2() {
+        let pool = ThreadPool::new(2);
+
+        let inner = pool.submit(|| 2);
+        let outer = pool.spawn_future(async move { inner.await.unwrap() * 21 });
+
+        assert_eq!(outer.join().unwrap(), 42);
+
+        let panicked = pool.spawn_future(async { panic!("async") });
+
+        assert_eq!(panicked.join().unwrap_err().panic_message(), Some("async"));
+    }
+
+    #[test]
+    fn thread_pool_test_run_async_1() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        /// Wakes up the thread blocked in `block_on`.
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        /// The smallest executor: poll, park, repeat.
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(Unpark(thread::current())));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+
+                thread::park();
+            }
+        }
+
+        let pool = ThreadPool::new(1);
+
+        let sum = block_on(async {
+            let a = pool.run_async(|| {
+                thread::sleep(Duration::from_millis(10));
+                20
+            });
+            let b = pool.run_async(|| 22);
+
+            a.await.unwrap() + b.await.unwrap()
+        });
+
+        assert_eq!(sum, 42);
+
+        // Dropping the future cancels a job that hasn't started.
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ran);
+
+        pool.run(move || gate_rx.recv().unwrap());
+        drop(pool.run_async(move || counter.fetch_add(1, Ordering::SeqCst)));
+        gate_tx.send(()).unwrap();
+        pool.shutdown();
+
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+
+        // After shutdown, the job is dropped right away.
+        assert!(matches!(
+            block_on(pool.run_async(|| ())),
+            Err(JoinError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn thread_pool_test_wait_idle_1() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .work_stealing(true)
+            .build()
+            .unwrap();
+        let pool = Arc::new(pool);
+        let count = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..10 {
+            let count = Arc::clone(&count);
+            let inner_pool = Arc::clone(&pool);
+
+            pool.run(move || {
+                thread::sleep(Duration::from_millis(2));
+
+                // Jobs queued by jobs are waited for too.
+                inner_pool.run(move || {
+                    count.fetch_add(1, Ordering::SeqCst);
+                });
+            });
+        }
+
+        pool.wait_idle();
+
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+        assert_eq!(pool.stats().queued_jobs, 0);
+
+        pool.shutdown();
+    }
+
+    #[test]
+    fn thread_pool_test_wait_idle_2() {
+        let pool = ThreadPool::new(1);
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+
+        pool.run(move || gate_rx.recv().unwrap());
+
+        assert!(!pool.wait_idle_timeout(Duration::from_millis(20)));
+
+        gate_tx.send(()).unwrap();
+
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+
+        let handle = pool.submit({
+            let pool = ThreadPool::new(1);
+
+            move || pool.wait_idle()
+        });
+
+        // Waiting on another pool from a job is fine.
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn thread_pool_test_wait_idle_3() {
+        // Kills the worker when dropped, see `thread_pool_test_respawn_1`.
+        struct Bomb;
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                panic!("bomb");
+            }
+        }
+
+        let pool = ThreadPool::new(1);
+
+        pool.run(|| std::panic::panic_any(Bomb));
+
+        // The job still counts as done, though its worker died.
+        assert!(pool.wait_idle_timeout(Duration::from_secs(1)));
+        assert_eq!(pool.submit(|| 42).join().unwrap(), 42);
+        assert!(pool.wait_idle_timeout(Duration::from_secs(1)));
+
+        // The dead thread may still be on its way out.
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while pool.stats().idle_workers > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let stats = pool.stats();
+
+        assert_eq!((stats.active_workers, stats.idle_workers), (0, 1));
+        assert_eq!((stats.panicked_jobs, stats.completed_jobs), (1, 1));
+    }
+
+    #[test]
+    fn thread_pool_test_group_1() {
+        let pool = ThreadPool::new(4);
+        let mut group = pool.task_group();
+
+        for i in 0..8u64 {
+            group.spawn(move || {
+                // Later jobs finish first, results still come back in order.
+                thread::sleep(Duration::from_millis(8 - i));
+
+                if i == 3 {
+                    panic!("job 3");
+                }
+
+                i
+            });
+        }
+
+        assert_eq!(group.len(), 8);
+
+        let results = group.wait();
+
+        for (i, result) in results.iter().enumerate() {
+            match result {
+                Ok(value) => assert_eq!(*value, i as u64),
+                Err(e) => {
+                    assert_eq!(i, 3);
+                    assert_eq!(e.panic_message(), Some("job 3"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thread_pool_test_group_2() {
+        let pool = ThreadPool::new(1);
+        let count = Arc::new(AtomicUsize::new(0));
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let mut group = pool.task_group();
+
+        group.spawn(move || {
+            gate_rx.recv().unwrap();
+            panic!("first job failed");
+        });
+
+        for _ in 0..3 {
+            let count = Arc::clone(&count);
+
+            group.spawn(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        // Turned on late, it still covers the jobs spawned so far.
+        let mut group = group.fail_fast(true);
+
+        gate_tx.send(()).unwrap();
+        pool.wait_idle();
+
+        // Jobs added after the failure aren't even queued.
+        let count_late = Arc::clone(&count);
+
+        group.spawn(move || {
+            count_late.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let results = group.wait();
+
+        assert!(matches!(results[0], Err(JoinError::Panicked(_))));
+        assert!(results[1..]
+            .iter()
+            .all(|result| matches!(result, Err(JoinError::Cancelled))));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn thread_pool_test_graph_1() {
+        let pool = ThreadPool::new(4);
+        let mut builder = TaskGraphBuilder::new();
+
+        let source = builder.add_node(|_| 10);
+        let double = builder.add_node(|inputs: &[&i32]| inputs[0] * 2);
+        let square = builder.add_node(|inputs: &[&i32]| inputs[0] * inputs[0]);
+        let diff = builder.add_node(|inputs: &[&i32]| inputs[0] - inputs[1]);
+        let broken = builder.add_node(|_| panic!("broken node"));
+        let after_broken = builder.add_node(|inputs: &[&i32]| *inputs[1]);
+        let last = builder.add_node(|inputs: &[&i32]| *inputs[0]);
+
+        builder
+            .add_dependency(double, source)
+            .add_dependency(square, source)
+            // Inputs come in the order the dependencies were added.
+            .add_dependency(diff, square)
+            .add_dependency(diff, double)
+            .add_dependency(after_broken, broken)
+            .add_dependency(after_broken, diff)
+            .add_dependency(last, after_broken);
+
+        let report = pool.run_graph(builder.build().unwrap());
+
+        assert_eq!(report.value(double), Some(&20));
+        assert_eq!(report.value(square), Some(&100));
+        assert_eq!(report.value(diff), Some(&80));
+        assert_eq!(report.failed(), vec![broken]);
+        assert_eq!(report.skipped(), vec![after_broken, last]);
+
+        match report.outcome(broken) {
+            NodeOutcome::Failed(e) => assert_eq!(e.panic_message(), Some("broken node")),
+            _ => panic!("broken node didn't fail"),
+        }
+
+        // The worker counts the panic after the node reports it.
+        pool.wait_idle();
+
+        assert_eq!(pool.stats().panicked_jobs, 1);
+    }
+
+    #[test]
+    fn thread_pool_test_graph_2() {
+        let mut builder = TaskGraphBuilder::<()>::new();
+        let a = builder.add_node(|_| ());
+        let b = builder.add_node(|_| ());
+        let c = builder.add_node(|_| ());
+        let d = builder.add_node(|_| ());
+
+        builder
+            .add_dependency(b, a)
+            .add_dependency(c, b)
+            .add_dependency(b, c)
+            .add_dependency(d, c);
+
+        assert_eq!(
+            builder.build().err(),
+            Some(GraphError::Cycle(vec![b, c, d]))
+        );
+
+        let mut builder = TaskGraphBuilder::<()>::new();
+        let a = builder.add_node(|_| ());
+        let mut other = TaskGraphBuilder::<()>::new();
+        other.add_node(|_| ());
+        let unknown = other.add_node(|_| ());
+
+        builder.add_dependency(a, unknown);
+
+        assert_eq!(
+            builder.build().err(),
+            Some(GraphError::UnknownNode(unknown))
+        );
+
+        // Run from the only worker, which has to run the nodes itself.
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner_pool = Arc::clone(&pool);
+
+        let handle = pool.submit(move || {
+            let mut builder = TaskGraphBuilder::new();
+            let first = builder.add_node(|_| 1);
+            let second = builder.add_node(|inputs: &[&i32]| inputs[0] + 1);
+
+            builder.add_dependency(second, first);
+
+            let report = inner_pool.run_graph(builder.build().unwrap());
+
+            report.into_outcomes().len()
+        });
+
+        assert_eq!(handle.join().unwrap(), 2);
     }
 }
@@ -0,0 +1,154 @@
+use crate::ThreadPool;
+
+/// Chunks per worker that `split` aims for, so a slow chunk can be balanced
+/// out by the others.
+const CHUNKS_PER_WORKER: usize = 4;
+
+impl ThreadPool {
+    /// Maps every item with `f` in parallel, keeping the order of `items`.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers: Vec<u64> = (1..=1000).collect();
+    ///
+    /// let squares = pool.par_map(&numbers, |n| n * n);
+    /// let even = pool.par_filter(&squares, |n| n % 2 == 0);
+    /// let sum = pool.par_reduce(&numbers, || 0, |a, b| a + b);
+    ///
+    /// assert_eq!(squares[9], 100);
+    /// assert_eq!(even.len(), 500);
+    /// assert_eq!(sum, 500_500);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics again with the payload of `f` if it panicked, after every chunk
+    /// is finished.
+    pub fn par_map<T, U, F>(&self, items: &[T], f: F) -> Vec<U>
+    where
+        T: Sync,
+        U: Send,
+        F: Fn(&T) -> U + Sync,
+    {
+        self.split(
+            items,
+            &|chunk: &[T]| chunk.iter().map(&f).collect(),
+            &|mut left: Vec<U>, right: Vec<U>| {
+                left.extend(right);
+                left
+            },
+        )
+    }
+
+    /// Calls `f` on every item in parallel.
+    ///
+    /// # Panics
+    ///
+    /// See `par_map()`.
+    pub fn par_for_each<T, F>(&self, items: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        self.split(
+            items,
+            &|chunk: &[T]| chunk.iter().for_each(&f),
+            &|(), ()| (),
+        )
+    }
+
+    /// Returns the items matching `predicate`, keeping the order of `items`.
+    ///
+    /// # Panics
+    ///
+    /// See `par_map()`.
+    pub fn par_filter<'a, T, P>(&self, items: &'a [T], predicate: P) -> Vec<&'a T>
+    where
+        T: Sync,
+        P: Fn(&T) -> bool + Sync,
+    {
+        self.split(
+            items,
+            &|chunk: &'a [T]| chunk.iter().filter(|item| predicate(item)).collect(),
+            &|mut left: Vec<&'a T>, right: Vec<&'a T>| {
+                left.extend(right);
+                left
+            },
+        )
+    }
+
+    /// Folds all items into one with `op`, in parallel.
+    ///
+    /// # Notes
+    ///
+    /// Every chunk starts from its own `identity()`, and the chunks are
+    /// combined in an unspecified grouping, so `op` should be associative
+    /// and `identity()` neutral to it, e.g. `0` for `+`.
+    ///
+    /// # Panics
+    ///
+    /// See `par_map()`.
+    pub fn par_reduce<T, ID, OP>(&self, items: &[T], identity: ID, op: OP) -> T
+    where
+        T: Clone + Send + Sync,
+        ID: Fn() -> T + Sync,
+        OP: Fn(T, T) -> T + Sync,
+    {
+        self.split(
+            items,
+            &|chunk: &[T]| chunk.iter().cloned().fold(identity(), &op),
+            &op,
+        )
+    }
+
+    /// Runs `leaf` on chunks of `items` in parallel and merges the results
+    /// with `combine`, in order.
+    ///
+    /// # Notes
+    ///
+    /// The slice is halved with `join()` until the chunks are small enough
+    /// for every worker to get a few of them. Idle workers pick up the
+    /// halves still queued, busy ones run them themselves.
+    fn split<'a, T, R, L, C>(&self, items: &'a [T], leaf: &L, combine: &C) -> R
+    where
+        T: Sync,
+        R: Send,
+        L: Fn(&'a [T]) -> R + Sync,
+        C: Fn(R, R) -> R + Sync,
+    {
+        let chunks = self.num_threads() * CHUNKS_PER_WORKER;
+        let min_len = (items.len() / chunks).max(1);
+
+        self.split_chunk(items, min_len, leaf, combine)
+    }
+
+    fn split_chunk<'a, T, R, L, C>(
+        &self,
+        items: &'a [T],
+        min_len: usize,
+        leaf: &L,
+        combine: &C,
+    ) -> R
+    where
+        T: Sync,
+        R: Send,
+        L: Fn(&'a [T]) -> R + Sync,
+        C: Fn(R, R) -> R + Sync,
+    {
+        if items.len() <= min_len {
+            return leaf(items);
+        }
+
+        let (left, right) = items.split_at(items.len() / 2);
+        let (left, right) = self.join(
+            || self.split_chunk(left, min_len, leaf, combine),
+            || self.split_chunk(right, min_len, leaf, combine),
+        );
+
+        combine(left, right)
+    }
+}
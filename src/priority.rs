@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::queue::Queued;
+use crate::Job;
+
+/// How urgent a job is, see `ThreadPool::run_with_priority`.
+///
+/// # Variants
+///
+/// - `High`: Latency-sensitive jobs, run before anything else queued.
+/// - `Normal`: What `ThreadPool::run` uses. The default.
+/// - `Low`: Batch jobs, run when nothing more urgent is waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// How long a queued job waits before it is treated like one of the next
+/// higher `Priority`, unless `ThreadPoolBuilder::priority_aging` says otherwise.
+pub(crate) const DEFAULT_AGING: Duration = Duration::from_millis(100);
+
+/// The global job queue, one FIFO per `Priority`.
+///
+/// # Notes
+///
+/// `pop` takes the front job of the most urgent level, but every `aging`
+/// a job waits moves it up one level. Once ranked the same, the older job
+/// goes first, so a steady stream of `High` jobs can't starve a `Low` one
+/// for more than two `aging` periods.
+pub(crate) struct PriorityQueue {
+    levels: [VecDeque<Queued>; 3],
+    len: usize,
+    aging: Duration,
+}
+
+impl PriorityQueue {
+    pub(crate) fn new(aging: Duration) -> PriorityQueue {
+        PriorityQueue {
+            levels: Default::default(),
+            len: 0,
+            aging,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn push(&mut self, priority: Priority, queued: Queued) {
+        self.levels[priority as usize].push_back(queued);
+        self.len += 1;
+    }
+
+    /// Takes the next job to run, see the `Notes` of `PriorityQueue`.
+    pub(crate) fn pop(&mut self) -> Option<Queued> {
+        let mut fronts = self
+            .levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, jobs)| jobs.front().map(|queued| (level, queued.since)))
+            .peekable();
+
+        let (first, _) = *fronts.peek()?;
+
+        let level = if self.len == self.levels[first].len() {
+            // Nothing else to compare with, skip `Instant::now()`.
+            first
+        } else {
+            let now = Instant::now();
+            let aging = self.aging.as_nanos().max(1);
+            let rank = |level: usize, since: Instant| {
+                let promoted = now.duration_since(since).as_nanos() / aging;
+
+                ((level as u128).saturating_sub(promoted), since)
+            };
+
+            fronts
+                .min_by_key(|&(level, since)| rank(level, since))
+                .map(|(level, _)| level)?
+        };
+
+        self.take(level)
+    }
+
+    /// The least urgent level with a job queued.
+    pub(crate) fn lowest(&self) -> Option<Priority> {
+        match self.levels.iter().rposition(|jobs| !jobs.is_empty())? {
+            0 => Some(Priority::High),
+            1 => Some(Priority::Normal),
+            _ => Some(Priority::Low),
+        }
+    }
+
+    /// Takes the oldest job of the least urgent level, to make room.
+    pub(crate) fn pop_lowest(&mut self) -> Option<Queued> {
+        let level = self.levels.iter().rposition(|jobs| !jobs.is_empty())?;
+
+        self.take(level)
+    }
+
+    /// Takes out every job, most urgent first.
+    pub(crate) fn drain(&mut self) -> Vec<Job> {
+        self.len = 0;
+        self.levels
+            .iter_mut()
+            .flat_map(|jobs| jobs.drain(..))
+            .map(|queued| queued.job)
+            .collect()
+    }
+
+    fn take(&mut self, level: usize) -> Option<Queued> {
+        let queued = self.levels[level].pop_front()?;
+
+        self.len -= 1;
+
+        Some(queued)
+    }
+}
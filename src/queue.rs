@@ -0,0 +1,651 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::priority::{PriorityQueue, DEFAULT_AGING};
+use crate::{Job, Message, Priority, SubmitError};
+
+/// What `ThreadPool::try_run` does when a bounded queue is full.
+///
+/// # Variants
+///
+/// - `Block`: Wait until a worker takes a job off the queue. The default.
+/// - `Reject`: Hand the job back in `SubmitError::Full`.
+/// - `CallerRuns`: Run the job right away on the submitting thread.
+/// - `DropOldest`: Drop the oldest queued job of the lowest `Priority` to make
+///   room. If every queued job is more urgent than the new one, the new one
+///   is dropped instead. The `TaskHandle` of a dropped job, if any, reports
+///   `JoinError::Disconnected`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Block,
+    Reject,
+    CallerRuns,
+    DropOldest,
+}
+
+/// Queue settings collected by `ThreadPoolBuilder`.
+///
+/// # Members
+///
+/// - `capacity`: Bounds the global queue, if set.
+/// - `policy`: What to do when it is full.
+/// - `work_stealing`: Give each worker a local deque, see `JobQueue`.
+/// - `aging`: How fast waiting jobs gain priority, see `PriorityQueue`.
+#[derive(Default)]
+pub(crate) struct QueueConfig {
+    pub(crate) capacity: Option<usize>,
+    pub(crate) policy: OverflowPolicy,
+    pub(crate) work_stealing: bool,
+    pub(crate) aging: Option<Duration>,
+}
+
+/// What is behind the `JobQueue` lock.
+///
+/// # Members
+///
+/// - `jobs`: Queued jobs, by priority and then oldest first.
+/// - `closed`: Set on shutdown. No job is pushed afterwards, and workers
+///   get `Message::Terminate` once every queue is empty.
+/// - `waking`: Workers already notified but not yet running again.
+/// - `blocked`: Submitters waiting on `not_full`.
+///
+/// # Notes
+///
+/// `JobQueue::idle`, `waking` and `blocked` let us skip `notify_one()` when
+/// nobody would get it, which is a syscall even then. Without `waking`, every
+/// push in a burst would notify the same sleeping worker again until it gets
+/// scheduled.
+struct State {
+    jobs: PriorityQueue,
+    closed: bool,
+    waking: usize,
+    blocked: usize,
+}
+
+/// A queued job and when it was queued, for `PoolStats::total_wait_time`.
+pub(crate) struct Queued {
+    pub(crate) job: Job,
+    pub(crate) since: Instant,
+}
+
+impl Queued {
+    fn new(job: Job) -> Queued {
+        Queued {
+            job,
+            since: Instant::now(),
+        }
+    }
+}
+
+/// The local deque of a worker, in work-stealing mode.
+///
+/// # Notes
+///
+/// The owner pushes and pops at the back, so the job it spawned last runs
+/// first, while it is still hot in cache. Thieves take from the front.
+pub(crate) struct Local {
+    id: usize,
+    jobs: Mutex<VecDeque<Queued>>,
+}
+
+/// The worker running on the current thread, if any.
+///
+/// # Members
+///
+/// - `queue`: The address of the owning `JobQueue`, so a job of one pool
+///   submitting to another pool doesn't end up in the wrong deque.
+/// - `id`: The worker `id`.
+/// - `local`: Its `Local` deque, in work-stealing mode.
+struct Current {
+    queue: usize,
+    id: usize,
+    local: Option<Arc<Local>>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+/// The job queue shared by all `Worker`s.
+///
+/// # Notes
+///
+/// A `PriorityQueue` behind a `Mutex`, with `Condvar`s for both sides. The lock is
+/// only held to push or pop a single job: idle workers sleep in
+/// `not_empty.wait()`, which releases it, instead of holding it while blocked
+/// like the old `Mutex<mpsc::Receiver>` did.
+///
+/// In work-stealing mode each worker also owns a `Local` deque. Jobs
+/// submitted from inside a worker go there, skipping the global lock, and
+/// idle workers steal from their peers before going to sleep. `local_jobs`
+/// counts the jobs in all of them, so nobody sleeps while one is left.
+///
+/// Only the global queue is bounded. A worker blocked on its own full queue
+/// would never drain it.
+///
+/// `closed` mirrors `State::closed`, so `push_local` can check it without
+/// taking the global lock.
+///
+/// `in_flight` counts the jobs queued or running, for `wait_idle`. A job
+/// counts from its push until `finish`, so the pool never looks idle while
+/// a job is on its way from the queue to a worker. `idle_lock` and
+/// `all_done` are only touched when it drops to `0`.
+pub(crate) struct JobQueue {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    work_stealing: bool,
+    locals: RwLock<Vec<Arc<Local>>>,
+    local_jobs: AtomicUsize,
+    idle: AtomicUsize,
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+    idle_lock: Mutex<()>,
+    all_done: Condvar,
+}
+
+impl JobQueue {
+    /// Creates an empty queue set up by `config`.
+    pub(crate) fn new(config: QueueConfig) -> JobQueue {
+        JobQueue {
+            state: Mutex::new(State {
+                jobs: PriorityQueue::new(config.aging.unwrap_or(DEFAULT_AGING)),
+                closed: false,
+                waking: 0,
+                blocked: 0,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: config.capacity,
+            policy: config.policy,
+            work_stealing: config.work_stealing,
+            locals: RwLock::new(Vec::new()),
+            local_jobs: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle_lock: Mutex::new(()),
+            all_done: Condvar::new(),
+        }
+    }
+
+    /// What to do when the queue is full.
+    pub(crate) fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Sets up the current thread as Worker `id`.
+    ///
+    /// In work-stealing mode, returns its `Local` deque. A respawned worker
+    /// gets the deque of the thread it replaces, jobs included.
+    pub(crate) fn register(&self, id: usize) -> Option<Arc<Local>> {
+        let local = if self.work_stealing {
+            let mut locals = self.locals.write().unwrap_or_else(PoisonError::into_inner);
+            let local = match locals.iter().find(|local| local.id == id) {
+                Some(local) => Arc::clone(local),
+                None => {
+                    let local = Arc::new(Local {
+                        id,
+                        jobs: Mutex::new(VecDeque::new()),
+                    });
+
+                    locals.push(Arc::clone(&local));
+                    local
+                }
+            };
+
+            Some(local)
+        } else {
+            None
+        };
+
+        CURRENT.with(|current| {
+            *current.borrow_mut() = Some(Current {
+                queue: self.address(),
+                id,
+                local: local.clone(),
+            });
+        });
+
+        local
+    }
+
+    /// Removes the `Local` deque of Worker `id`, which is exiting for good.
+    ///
+    /// Its jobs go to the global queue, even past its capacity.
+    pub(crate) fn unregister(&self, id: usize) {
+        let local = {
+            let mut locals = self.locals.write().unwrap_or_else(PoisonError::into_inner);
+
+            match locals.iter().position(|local| local.id == id) {
+                Some(i) => locals.remove(i),
+                None => return,
+            }
+        };
+
+        let mut state = self.lock();
+
+        while let Some(queued) = self.pop_local(&local, VecDeque::pop_front) {
+            state.jobs.push(Priority::Normal, queued);
+        }
+
+        let backlog = state.jobs.len() + self.local_jobs.load(Ordering::SeqCst);
+
+        self.wake_one(state, backlog);
+    }
+
+    /// The `id` and `Local` deque of the current thread, if it is a worker
+    /// of this queue.
+    pub(crate) fn current_worker(&self) -> Option<(usize, Option<Arc<Local>>)> {
+        CURRENT.with(|current| match &*current.borrow() {
+            Some(current) if current.queue == self.address() => {
+                Some((current.id, current.local.clone()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Identifies this queue in `Current`.
+    fn address(&self) -> usize {
+        self as *const JobQueue as usize
+    }
+
+    /// Queues `f` at `priority`, following the `OverflowPolicy` if the queue
+    /// is full.
+    ///
+    /// Called from a work-stealing worker of this queue, a `Normal` job goes
+    /// to the worker's `Local` deque instead. Others skip it, since the
+    /// deques know nothing about priorities.
+    ///
+    /// # Errors
+    ///
+    /// - `SubmitError::Shutdown`: The queue is closed.
+    /// - `SubmitError::Full`: The queue is full and the policy is `Reject` or
+    ///   `CallerRuns`. Running the job is left to the caller.
+    ///
+    /// # Notes
+    ///
+    /// `f` is only boxed once it is accepted, so it can be handed back as is.
+    pub(crate) fn push<F>(&self, priority: Priority, f: F) -> Result<(), SubmitError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let f = match priority {
+            Priority::Normal => match self.push_local(f) {
+                Ok(()) => return Ok(()),
+                Err(f) => f,
+            },
+            _ => f,
+        };
+
+        let mut evicted = None;
+        let mut state = self.lock();
+
+        loop {
+            if state.closed {
+                return Err(SubmitError::Shutdown(f));
+            }
+
+            match self.capacity {
+                Some(capacity) if state.jobs.len() >= capacity => match self.policy {
+                    OverflowPolicy::Block => {
+                        state.blocked += 1;
+                        state = self
+                            .not_full
+                            .wait(state)
+                            .unwrap_or_else(PoisonError::into_inner);
+                        state.blocked -= 1;
+                    }
+                    OverflowPolicy::Reject | OverflowPolicy::CallerRuns => {
+                        return Err(SubmitError::Full(f));
+                    }
+                    OverflowPolicy::DropOldest => match state.jobs.lowest() {
+                        // Evicting a more urgent job would invert priorities.
+                        Some(lowest) if priority > lowest => {
+                            drop(state);
+                            drop(f);
+
+                            return Ok(());
+                        }
+                        _ => evicted = state.jobs.pop_lowest(),
+                    },
+                },
+                _ => break,
+            }
+        }
+
+        // The evicted job makes room in `in_flight` too.
+        if evicted.is_none() {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+        }
+
+        state.jobs.push(priority, Queued::new(Box::new(f)));
+
+        // Workers on their way will take a job each, only wake more for the rest.
+        let backlog = state.jobs.len() + self.local_jobs.load(Ordering::SeqCst);
+
+        self.wake_one(state, backlog);
+
+        // Dropped out of the lock, it may drop a `Completer` or anything else.
+        drop(evicted);
+
+        Ok(())
+    }
+
+    /// Pushes `f` to the current worker's `Local` deque, if there is one.
+    ///
+    /// Hands `f` back if the current thread isn't a work-stealing worker of
+    /// this queue.
+    fn push_local<F>(&self, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !self.work_stealing {
+            return Err(f);
+        }
+
+        let local = match self.current_worker() {
+            Some((_, Some(local))) => local,
+            _ => return Err(f),
+        };
+
+        let mut jobs = local.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Checked under the deque lock, which `close` takes to drain it after
+        // setting `closed`: either it drains our job, or we see the flag.
+        // The global path then reports `SubmitError::Shutdown`.
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(f);
+        }
+
+        // Before the push, and under the lock: once it is released, a thief
+        // (or `close`) may take the job and count it out right away.
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        // Also pairs with `idle` / `local_jobs` in `pop`: either we see the
+        // sleeper, or it sees our job.
+        self.local_jobs.fetch_add(1, Ordering::SeqCst);
+
+        jobs.push_back(Queued::new(Box::new(f)));
+
+        drop(jobs);
+
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            let state = self.lock();
+            let backlog = state.jobs.len() + self.local_jobs.load(Ordering::SeqCst);
+
+            self.wake_one(state, backlog);
+        }
+
+        Ok(())
+    }
+
+    /// Notifies one idle worker, unless those already waking cover `backlog`.
+    fn wake_one(&self, mut state: MutexGuard<'_, State>, backlog: usize) {
+        let wake = self.idle.load(Ordering::SeqCst) > state.waking && backlog > state.waking;
+
+        if wake {
+            state.waking += 1;
+        }
+
+        drop(state);
+
+        if wake {
+            self.not_empty.notify_one();
+        }
+    }
+
+    /// Takes the next job, blocking until there is one.
+    ///
+    /// # Steps
+    ///
+    /// 1. Return `Message::Terminate` if `retire` is set.
+    /// 2. The newest job of `local`, the caller's own deque.
+    /// 3. The next job of the global queue, see `PriorityQueue`.
+    /// 4. The oldest job of a peer's deque.
+    /// 5. Otherwise wait, or return `Message::Terminate` once the queue is
+    ///    closed and every deque is empty, or once `keep_alive` has passed
+    ///    without a job.
+    pub(crate) fn pop(
+        &self,
+        local: Option<&Local>,
+        retire: &AtomicBool,
+        keep_alive: Option<Duration>,
+    ) -> Message {
+        let mut timed_out = false;
+
+        loop {
+            if retire.load(Ordering::SeqCst) {
+                return Message::Terminate;
+            }
+
+            if let Some(queued) = self.try_pop(local) {
+                return Message::NewJob(queued);
+            }
+
+            let mut state = self.lock();
+
+            if !state.jobs.is_empty() {
+                continue;
+            }
+
+            if self.local_jobs.load(Ordering::SeqCst) > 0 {
+                drop(state);
+
+                // Someone is about to pop it, or has just pushed it.
+                thread::yield_now();
+                continue;
+            }
+
+            if state.closed {
+                return Message::Terminate;
+            }
+
+            // Checked under the lock, so `wake_all` can't slip in before the wait.
+            if retire.load(Ordering::SeqCst) || timed_out {
+                return Message::Terminate;
+            }
+
+            self.idle.fetch_add(1, Ordering::SeqCst);
+
+            if self.local_jobs.load(Ordering::SeqCst) == 0 {
+                state = match keep_alive {
+                    Some(keep_alive) => {
+                        let (state, result) = self
+                            .not_empty
+                            .wait_timeout(state, keep_alive)
+                            .unwrap_or_else(PoisonError::into_inner);
+
+                        timed_out = result.timed_out();
+                        state
+                    }
+                    None => self
+                        .not_empty
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner),
+                };
+
+                // Even on a timeout: a `notify_one` may have raced with it, and
+                // a `waking` stuck above 0 would silence every later push.
+                // Under-counting only costs a spare notify.
+                state.waking = state.waking.saturating_sub(1);
+            }
+
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Takes the next job like `pop`, but returns `None` instead of waiting.
+    pub(crate) fn try_pop(&self, local: Option<&Local>) -> Option<Queued> {
+        if let Some(local) = local {
+            if let Some(job) = self.pop_local(local, VecDeque::pop_back) {
+                return Some(job);
+            }
+        }
+
+        let mut state = self.lock();
+
+        if let Some(job) = state.jobs.pop() {
+            let wake = state.blocked > 0;
+
+            drop(state);
+
+            if wake {
+                self.not_full.notify_one();
+            }
+
+            return Some(job);
+        }
+
+        drop(state);
+
+        if self.local_jobs.load(Ordering::SeqCst) > 0 {
+            return self.steal(local);
+        }
+
+        None
+    }
+
+    /// Takes a job off a peer's `Local` deque, starting after `local`.
+    fn steal(&self, local: Option<&Local>) -> Option<Queued> {
+        let locals = self.locals.read().unwrap_or_else(PoisonError::into_inner);
+        let start = local
+            .and_then(|local| locals.iter().position(|peer| peer.id == local.id))
+            .map_or(0, |i| i + 1);
+
+        (0..locals.len())
+            .map(|i| &locals[(start + i) % locals.len()])
+            .filter(|peer| !matches!(local, Some(local) if local.id == peer.id))
+            .find_map(|peer| self.pop_local(peer, VecDeque::pop_front))
+    }
+
+    /// Pops a job off `local` with `pop`, keeping `local_jobs` in sync.
+    fn pop_local(
+        &self,
+        local: &Local,
+        pop: fn(&mut VecDeque<Queued>) -> Option<Queued>,
+    ) -> Option<Queued> {
+        let job = pop(&mut local.jobs.lock().unwrap_or_else(PoisonError::into_inner))?;
+
+        self.local_jobs.fetch_sub(1, Ordering::SeqCst);
+
+        Some(job)
+    }
+
+    /// The number of queued jobs, local deques included.
+    pub(crate) fn len(&self) -> usize {
+        self.lock().jobs.len() + self.local_jobs.load(Ordering::SeqCst)
+    }
+
+    /// Whether a job is waiting with no idle worker to take it.
+    pub(crate) fn needs_worker(&self) -> bool {
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            return false;
+        }
+
+        !self.lock().jobs.is_empty() || self.local_jobs.load(Ordering::SeqCst) > 0
+    }
+
+    /// Wakes up every idle worker, e.g. for them to see their `retire` flag.
+    pub(crate) fn wake_all(&self) {
+        drop(self.lock());
+
+        self.not_empty.notify_all();
+    }
+
+    /// Whether `close` was called.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Closes the queue, waking up everyone waiting on it.
+    ///
+    /// If `discard` is set, the queued jobs, local deques included, are taken
+    /// out and returned, so workers exit as soon as their current job is done.
+    pub(crate) fn close(&self, discard: bool) -> Vec<Job> {
+        let mut state = self.lock();
+        let mut jobs = Vec::new();
+
+        state.closed = true;
+        self.closed.store(true, Ordering::SeqCst);
+
+        if discard {
+            jobs.extend(state.jobs.drain());
+
+            let locals = self.locals.read().unwrap_or_else(PoisonError::into_inner);
+
+            for local in locals.iter() {
+                while let Some(queued) = self.pop_local(local, VecDeque::pop_front) {
+                    jobs.push(queued.job);
+                }
+            }
+        }
+
+        drop(state);
+
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+        self.finish(jobs.len());
+
+        jobs
+    }
+
+    /// Counts `count` jobs as done with, run or discarded.
+    pub(crate) fn finish(&self, count: usize) {
+        if count > 0 && self.in_flight.fetch_sub(count, Ordering::SeqCst) == count {
+            let _guard = self
+                .idle_lock
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            self.all_done.notify_all();
+        }
+    }
+
+    /// Blocks until no job is queued or running, or `deadline` passes.
+    ///
+    /// Returns `true` if the queue went idle.
+    pub(crate) fn wait_idle(&self, deadline: Option<Instant>) -> bool {
+        let mut guard = self
+            .idle_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        return false;
+                    }
+
+                    self.all_done
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .all_done
+                    .wait(guard)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        true
+    }
+
+    /// Locks the state.
+    ///
+    /// No user code runs under the lock, but don't let a poisoned lock turn
+    /// into an endless respawn loop.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
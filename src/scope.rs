@@ -0,0 +1,194 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::latch::Latch;
+use crate::{rethrow_reported, Job, ThreadPool};
+
+/// What a `Scope` waits on.
+///
+/// # Members
+///
+/// - `pending`: Spawned jobs that haven't finished or been dropped yet, plus
+///   one for the scope's closure until it returns.
+/// - `done`: Set when `pending` drops to `0`.
+/// - `panic`: The payload of the first job that panicked.
+///
+/// # Notes
+///
+/// Thanks to the extra count, `pending` can't reach `0` while the closure may
+/// still spawn jobs, so `done` is only set once.
+struct ScopeState {
+    pending: AtomicUsize,
+    done: Latch,
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+impl ScopeState {
+    /// Keeps the first panic payload, drops the others.
+    fn set_panic(&self, payload: Box<dyn Any + Send + 'static>) {
+        let mut panic = self.panic.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if panic.is_none() {
+            *panic = Some(payload);
+        }
+    }
+
+    /// Counts one job (or the closure) as finished.
+    fn finish(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.done.set();
+        }
+    }
+}
+
+/// A job spawned in a `Scope`.
+///
+/// # Notes
+///
+/// `pending` is only decremented in `drop`, after `f` has run or has been
+/// dropped. So even a job that escapes the pool unrun, e.g. through
+/// `ThreadPool::shutdown_now()`, keeps the scope waiting for as long as it
+/// holds its borrows.
+struct ScopedJob<F> {
+    f: Option<F>,
+    state: Arc<ScopeState>,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(mut self) {
+        let f = self.f.take().unwrap();
+
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            self.state.set_panic(payload);
+            rethrow_reported("scoped job panicked, payload sent to its scope");
+        }
+    }
+}
+
+impl<F> Drop for ScopedJob<F> {
+    fn drop(&mut self) {
+        if self.f.take().is_some() {
+            self.state
+                .set_panic(Box::new("scoped job was dropped without running"));
+        }
+
+        self.state.finish();
+    }
+}
+
+/// A scope to spawn jobs that borrow from the caller's stack.
+///
+/// # Notes
+///
+/// Created by `ThreadPool::scope()`. Like `std::thread::Scope`, `'scope` is
+/// the lifetime of the scope itself and `'env` the lifetime of what its jobs
+/// may borrow.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Runs `f` in the pool. It may borrow anything that outlives the scope.
+    ///
+    /// # Notes
+    ///
+    /// If the pool can't take the job (see `ThreadPool::try_run`), the scope
+    /// panics once it ends, like it does when a job panics.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.state.pending.fetch_add(1, Ordering::SeqCst);
+
+        let job = ScopedJob {
+            f: Some(f),
+            state: Arc::clone(&self.state),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+
+        // SAFETY: `ThreadPool::scope` doesn't return before `ScopedJob::drop`
+        // ran for every job, so nothing borrowed by `f` is used after 'scope.
+        let job: Job = unsafe { mem::transmute(job) };
+
+        let _ = self.pool.try_run(job);
+    }
+}
+
+impl ThreadPool {
+    /// Creates a scope in which jobs may borrow from the caller's stack.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let mut numbers = vec![1, 2, 3, 4];
+    ///
+    /// pool.scope(|s| {
+    ///     for n in numbers.iter_mut() {
+    ///         s.spawn(move || *n *= 2);
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(numbers, vec![2, 4, 6, 8]);
+    /// ```
+    ///
+    /// # Steps
+    ///
+    /// 1. Run `f` on the calling thread, which spawns jobs through the `Scope`.
+    /// 2. Wait until every spawned job is finished, even if `f` panicked.
+    ///    Called from one of this pool's workers, run queued jobs meanwhile,
+    ///    like `join`, so nested scopes can't take every worker.
+    /// 3. Return what `f` returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics again with the payload of `f`, or else of the first job that
+    /// panicked, after all the jobs are finished.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(1),
+                done: Latch::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        scope.state.finish();
+        self.wait_helping(&scope.state.done);
+
+        let value = match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+
+        let job_panic = scope
+            .state
+            .panic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        if let Some(payload) = job_panic {
+            panic::resume_unwind(payload);
+        }
+
+        value
+    }
+}
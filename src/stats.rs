@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// A snapshot of the pool's counters, returned by `ThreadPool::stats()`.
+///
+/// # Members
+///
+/// - `queued_jobs`: Jobs waiting for a worker, local deques included.
+/// - `active_workers`: Worker threads running a job.
+/// - `idle_workers`: Worker threads waiting for a job.
+/// - `completed_jobs`: Jobs that returned normally.
+/// - `panicked_jobs`: Jobs that panicked instead of returning.
+/// - `respawned_workers`: Worker threads that died and were replaced.
+/// - `total_run_time`, `avg_run_time`: Time spent running jobs, over both
+///   `completed_jobs` and `panicked_jobs`.
+/// - `total_wait_time`, `avg_wait_time`: Time jobs spent in the queue
+///   before a worker took them.
+///
+/// # Notes
+///
+/// The counters are read one by one while the pool keeps running, so they
+/// may be slightly out of step with each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub queued_jobs: usize,
+    pub active_workers: usize,
+    pub idle_workers: usize,
+    pub completed_jobs: usize,
+    pub panicked_jobs: usize,
+    pub respawned_workers: usize,
+    pub total_run_time: Duration,
+    pub avg_run_time: Duration,
+    pub total_wait_time: Duration,
+    pub avg_wait_time: Duration,
+}
+
+/// The live counters behind `PoolStats`, shared by the pool and its `Worker`s.
+///
+/// # Notes
+///
+/// Only plain statistics live here, so `Ordering::Relaxed` is enough.
+/// Times are kept in nanoseconds.
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) completed_jobs: AtomicUsize,
+    pub(crate) panicked_jobs: AtomicUsize,
+    pub(crate) respawned_workers: AtomicUsize,
+    pub(crate) active_workers: AtomicUsize,
+    run_nanos: AtomicU64,
+    started_jobs: AtomicUsize,
+    wait_nanos: AtomicU64,
+}
+
+impl Counters {
+    /// Counts a job that ran for `elapsed`.
+    pub(crate) fn record_run(&self, elapsed: Duration, panicked: bool) {
+        let finished = if panicked {
+            &self.panicked_jobs
+        } else {
+            &self.completed_jobs
+        };
+
+        finished.fetch_add(1, Ordering::Relaxed);
+        self.run_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a job taken off the queue after waiting for `waited`.
+    pub(crate) fn record_wait(&self, waited: Duration) {
+        self.started_jobs.fetch_add(1, Ordering::Relaxed);
+        self.wait_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Takes a `PoolStats` snapshot.
+    ///
+    /// `queued_jobs` and `live_workers` come from elsewhere in the pool.
+    pub(crate) fn snapshot(&self, queued_jobs: usize, live_workers: usize) -> PoolStats {
+        let completed_jobs = self.completed_jobs.load(Ordering::Relaxed);
+        let panicked_jobs = self.panicked_jobs.load(Ordering::Relaxed);
+        let active_workers = self.active_workers.load(Ordering::Relaxed);
+        let total_run_time = Duration::from_nanos(self.run_nanos.load(Ordering::Relaxed));
+        let total_wait_time = Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed));
+
+        PoolStats {
+            queued_jobs,
+            active_workers,
+            idle_workers: live_workers.saturating_sub(active_workers),
+            completed_jobs,
+            panicked_jobs,
+            respawned_workers: self.respawned_workers.load(Ordering::Relaxed),
+            total_run_time,
+            avg_run_time: average(total_run_time, completed_jobs + panicked_jobs),
+            total_wait_time,
+            avg_wait_time: average(total_wait_time, self.started_jobs.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// `total / count`, or zero if nothing was counted yet.
+fn average(total: Duration, count: usize) -> Duration {
+    if count == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_nanos((total.as_nanos() / count as u128) as u64)
+}
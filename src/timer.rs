@@ -0,0 +1,410 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::convert::TryFrom;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Job, Priority, Shared, ThreadPool};
+
+/// What a `Task` runs when it is due.
+///
+/// # Variants
+///
+/// - `Once`: Runs a single time.
+/// - `Periodic`: Runs every `period`. `running` is set while a run is in the
+///   pool, so a slow run makes the next one skip instead of piling up.
+enum Action {
+    Once(Job),
+    Periodic {
+        f: Arc<dyn Fn() + Send + Sync + 'static>,
+        period: Duration,
+        running: Arc<AtomicBool>,
+    },
+}
+
+/// A scheduled job, shared by the timer and its `ScheduledHandle`.
+///
+/// # Notes
+///
+/// `cancel` drops `action` right away, but the heap entry stays until it is
+/// due, where the timer finds nothing to do and forgets it.
+struct Task {
+    action: Mutex<Option<Action>>,
+}
+
+impl Task {
+    fn lock(&self) -> MutexGuard<'_, Option<Action>> {
+        self.action.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A `Task` in the timer heap.
+struct Entry {
+    at: Instant,
+    seq: u64,
+    task: Arc<Task>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    /// Reversed, so the `BinaryHeap` pops the earliest entry. `seq` keeps
+    /// entries due at the same time in scheduling order.
+    fn cmp(&self, other: &Entry) -> CmpOrdering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// What is behind the `Timer` lock.
+///
+/// # Members
+///
+/// - `heap`: Scheduled tasks, earliest first.
+/// - `seq`: Counts the entries pushed so far.
+/// - `shutdown`: Set by `Timer::shutdown()`, the timer thread exits.
+/// - `thread`: The timer thread, once the first task is scheduled.
+struct State {
+    heap: BinaryHeap<Entry>,
+    seq: u64,
+    shutdown: bool,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl State {
+    fn push(&mut self, at: Instant, task: Arc<Task>) {
+        let seq = self.seq;
+
+        self.seq += 1;
+        self.heap.push(Entry { at, seq, task });
+    }
+}
+
+/// The timer of a `ThreadPool`.
+///
+/// # Notes
+///
+/// A single thread sleeps until the earliest task is due, then hands it to
+/// the pool like `ThreadPool::run` and goes back to sleep. It never runs
+/// the jobs itself, so a slow job doesn't delay the others. The thread is
+/// only started by the first `schedule_*` call.
+pub(crate) struct Timer {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Timer {
+    pub(crate) fn new() -> Timer {
+        Timer {
+            state: Mutex::new(State {
+                heap: BinaryHeap::new(),
+                seq: 0,
+                shutdown: false,
+                thread: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Schedules `action` at `at`, starting the timer thread if needed.
+    ///
+    /// After `shutdown()`, the returned handle is already cancelled.
+    fn schedule(&self, shared: &Arc<Shared>, at: Instant, action: Action) -> ScheduledHandle {
+        let task = Arc::new(Task {
+            action: Mutex::new(Some(action)),
+        });
+        let handle = ScheduledHandle {
+            task: Arc::clone(&task),
+        };
+        let mut state = self.lock();
+
+        if state.shutdown {
+            drop(state);
+            handle.cancel();
+
+            return handle;
+        }
+
+        if state.thread.is_none() {
+            let shared = Arc::clone(shared);
+            let thread = thread::Builder::new()
+                .name("thread-pool-timer".to_string())
+                .spawn(move || shared.timer.run(&shared))
+                .expect("failed to spawn timer thread");
+
+            state.thread = Some(thread);
+        }
+
+        state.push(at, task);
+
+        drop(state);
+        self.changed.notify_one();
+
+        handle
+    }
+
+    /// The timer thread loop.
+    fn run(&self, shared: &Arc<Shared>) {
+        let mut state = self.lock();
+
+        loop {
+            if state.shutdown {
+                return;
+            }
+
+            let now = Instant::now();
+
+            match state.heap.peek() {
+                None => {
+                    state = self
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                Some(entry) if entry.at > now => {
+                    let timeout = entry.at - now;
+
+                    state = self
+                        .changed
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+                Some(_) => {
+                    let entry = state.heap.pop().unwrap();
+
+                    // Submitting may block on a full queue, don't hold up `schedule`.
+                    drop(state);
+
+                    let next = self.fire(shared, &entry, now);
+
+                    state = self.lock();
+
+                    if let Some(at) = next {
+                        state.push(at, entry.task);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands the job of `entry` to the pool.
+    ///
+    /// Returns when to run it next, if it is periodic and not cancelled.
+    /// A task whose next tick is too far out to represent is dropped.
+    fn fire(&self, shared: &Arc<Shared>, entry: &Entry, now: Instant) -> Option<Instant> {
+        let mut action = entry.task.lock();
+
+        let (f, period, running) = match action.take()? {
+            Action::Once(job) => {
+                drop(action);
+
+                let _ = shared.push(Priority::Normal, job);
+
+                return None;
+            }
+            Action::Periodic { f, period, running } => {
+                let copy = (Arc::clone(&f), period, Arc::clone(&running));
+
+                *action = Some(Action::Periodic { f, period, running });
+
+                copy
+            }
+        };
+
+        // Don't hold up `cancel()` while submitting.
+        drop(action);
+
+        if !running.swap(true, Ordering::SeqCst) {
+            let reset = Running(running);
+
+            let _ = shared.push(Priority::Normal, move || {
+                let _reset = reset;
+
+                f();
+            });
+        }
+
+        // Fixed rate: keep to the original ticks, skipping the missed ones.
+        // In nanoseconds, as a short period and a long stall overflow `u32`.
+        let period = period.as_nanos();
+        let missed = now.duration_since(entry.at).as_nanos() / period;
+        let offset = period.checked_mul(missed + 1)?;
+        let secs = u64::try_from(offset / 1_000_000_000).ok()?;
+        let offset = Duration::new(secs, (offset % 1_000_000_000) as u32);
+
+        entry.at.checked_add(offset)
+    }
+
+    /// Stops the timer thread and drops every task not yet due.
+    pub(crate) fn shutdown(&self) {
+        let mut state = self.lock();
+
+        state.shutdown = true;
+
+        let heap = mem::take(&mut state.heap);
+        let thread = state.thread.take();
+
+        drop(state);
+        self.changed.notify_one();
+
+        for entry in heap {
+            entry.task.lock().take();
+        }
+
+        if let Some(thread) = thread {
+            // The last `ThreadPool` handle may be dropped by a job run inline
+            // by the timer, under `OverflowPolicy::CallerRuns`.
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Clears the `running` flag of a periodic task once its run is over, even
+/// if it panicked.
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Handle to a job scheduled with `ThreadPool::schedule_after` and friends.
+///
+/// # Notes
+///
+/// Dropping the handle doesn't cancel the job.
+pub struct ScheduledHandle {
+    task: Arc<Task>,
+}
+
+impl ScheduledHandle {
+    /// Cancels the job, dropping it.
+    ///
+    /// A run already handed to the pool still goes on, but a periodic job
+    /// isn't run again.
+    pub fn cancel(&self) {
+        let action = self.task.lock().take();
+
+        drop(action);
+    }
+
+    /// Whether the job is cancelled, or was a one-shot job already handed
+    /// to the pool.
+    pub fn is_cancelled(&self) -> bool {
+        self.task.lock().is_none()
+    }
+}
+
+impl ThreadPool {
+    /// Runs `f` in the pool once `delay` has passed.
+    ///
+    /// # Usage
+    ///
+    /// ```
+    /// use rust_thread_pool::ThreadPool;
+    /// use std::sync::mpsc;
+    /// use std::time::Duration;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let (tx, rx) = mpsc::channel();
+    ///
+    /// let tick_tx = tx.clone();
+    /// let ticks = pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(10), move || {
+    ///     let _ = tick_tx.send("tick");
+    /// });
+    /// pool.schedule_after(Duration::from_millis(25), move || tx.send("later").unwrap());
+    ///
+    /// assert_eq!(rx.recv().unwrap(), "tick");
+    /// ticks.cancel();
+    /// ```
+    ///
+    /// # Notes
+    ///
+    /// The job goes through the queue like `run`, so it starts as soon as
+    /// a worker is free, not necessarily right on time. Jobs not yet due when
+    /// the pool shuts down are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timer thread can't be spawned.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.schedule_at(Instant::now() + delay, f)
+    }
+
+    /// Runs `f` in the pool once `at` has come.
+    ///
+    /// # Notes
+    ///
+    /// See `schedule_after()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timer thread can't be spawned.
+    pub fn schedule_at<F>(&self, at: Instant, f: F) -> ScheduledHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .timer
+            .schedule(&self.shared, at, Action::Once(Box::new(f)))
+    }
+
+    /// Runs `f` in the pool after `initial`, then every `period`, until the
+    /// returned handle is cancelled or the pool shuts down.
+    ///
+    /// # Notes
+    ///
+    /// The runs keep to the original ticks, however long each one takes. If
+    /// a run is still going when the next one is due, that one is skipped,
+    /// so runs never overlap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, or the timer thread can't be spawned.
+    pub fn schedule_at_fixed_rate<F>(
+        &self,
+        initial: Duration,
+        period: Duration,
+        f: F,
+    ) -> ScheduledHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(period > Duration::ZERO, "period must be greater than 0");
+
+        let action = Action::Periodic {
+            f: Arc::new(f),
+            period,
+            running: Arc::new(AtomicBool::new(false)),
+        };
+
+        self.shared
+            .timer
+            .schedule(&self.shared, Instant::now() + initial, action)
+    }
+}